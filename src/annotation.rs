@@ -0,0 +1,209 @@
+use crate::database::{ensure_annotation_schema, get_sources, open_writable};
+use crate::environment::*;
+use crate::history::History;
+use rusqlite::{params, Connection};
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::{Command, Stdio};
+
+/// The line of the edited file listing the tags, the other lines are the note
+const TAGS_PREFIX: &str = "tags:";
+
+/// Replace the tags and the note of the command, an empty note removes it
+pub fn save_annotation(
+    conn: &Connection,
+    command_id: i64,
+    tags: &[String],
+    note: &str,
+) -> rusqlite::Result<()> {
+    ensure_annotation_schema(conn)?;
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "delete from command_tags where command_id = ?1",
+        params![command_id],
+    )?;
+    for tag in tags {
+        tx.execute(
+            "insert into command_tags (command_id, tag) values (?1, ?2)",
+            params![command_id, tag],
+        )?;
+    }
+    match note.is_empty() {
+        true => tx.execute(
+            "delete from command_notes where command_id = ?1",
+            params![command_id],
+        )?,
+        false => tx.execute(
+            "insert or replace into command_notes (command_id, note) values (?1, ?2)",
+            params![command_id, note],
+        )?,
+    };
+    return tx.commit();
+}
+
+/// The file the tags and the note of the entry are edited in
+fn format_annotation(history: &History) -> String {
+    let mut text = String::from("# Tags and note of the command\n");
+    for line in history.shown_cmd.lines() {
+        text.push_str(&format!("#   {}\n", line));
+    }
+    text.push_str("# Tags are separated by spaces, the lines below them are the note.\n");
+    text.push_str(&format!("{} {}\n", TAGS_PREFIX, history.tags.join(" ")));
+    if let Some(note) = &history.note {
+        text.push_str(note);
+        text.push('\n');
+    }
+    return text;
+}
+
+/// Get the tags and the note of the edited file, lines starting with `#` are ignored
+fn parse_annotation(text: &str) -> (Vec<String>, String) {
+    let mut tags: Vec<String> = Vec::new();
+    let mut note = Vec::new();
+    for line in text.lines().filter(|l| !l.starts_with('#')) {
+        match line.strip_prefix(TAGS_PREFIX) {
+            Some(line_tags) if note.is_empty() => {
+                tags = line_tags
+                    .split_whitespace()
+                    .map(|t| t.to_string())
+                    .collect();
+            }
+            _ => note.push(line),
+        }
+    }
+    tags.sort();
+    tags.dedup();
+    return (tags, note.join("\n").trim().to_string());
+}
+
+/// The database the entry was loaded from, if it is the first one. The others are only read,
+/// so annotating their commands must not create tables in them.
+fn get_main_database_of(history: &History) -> Result<String, String> {
+    let databases = get_histdb_databases();
+    let main = get_sources(&databases)
+        .into_iter()
+        .next()
+        .ok_or("Cannot find the database")?;
+    if main.name != history.source {
+        return Err(format!(
+            "Only the commands of {} can be annotated, this one is from {}",
+            main.name, history.source
+        ));
+    }
+    return Ok(databases[0].clone());
+}
+
+/// Edit the tags and the note of the command in the editor, they are saved when it exits
+/// successfully. The editor uses the terminal, as the output of the picker is captured.
+pub fn edit_annotation(history: &History) -> Result<(), String> {
+    let database = get_main_database_of(history)?;
+    let path = env::temp_dir().join(format!("zsh-histdb-skim-{}.note", std::process::id()));
+    let error = |e: std::io::Error| format!("Cannot edit {} {}", path.display(), e);
+    // a file left over by an earlier run with the same process id
+    let _ = std::fs::remove_file(&path);
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all(format_annotation(history).as_bytes()))
+        .map_err(error)?;
+
+    let tty = |write: bool| {
+        return OpenOptions::new()
+            .read(!write)
+            .write(write)
+            .open("/dev/tty")
+            .map(Stdio::from)
+            .unwrap_or(Stdio::inherit());
+    };
+    let editor = get_editor();
+    let status = Command::new("sh")
+        .args(["-c", &format!("{} \"$1\"", editor), "sh"])
+        .arg(&path)
+        .stdin(tty(false))
+        .stdout(tty(true))
+        .status();
+    let text = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(_) => return Err(format!("{} failed, the note was not saved", editor)),
+        Err(e) => return Err(format!("Cannot run {} {}", editor, e)),
+    }
+    let (tags, note) = parse_annotation(&text.map_err(error)?);
+    let conn = open_writable(&database).map_err(|e| format!("Cannot open database {}", e))?;
+    save_annotation(&conn, history.command_id, &tags, &note)
+        .map_err(|e| format!("Cannot save the note {}", e))?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{add_history, ensure_schema, has_annotations, insert_command, Source};
+    use crate::filter::Filter;
+    use crate::grouping::Grouping;
+    use crate::location::Location;
+    use crate::query::{build_federated_query_string, QueryContext};
+
+    #[test]
+    fn edited_file() {
+        let (tags, note) = parse_annotation(
+            "# Tags and note of the command\n#   make\ntags: prod  deploy prod\n\nRun it twice.\ntags: no tag\n",
+        );
+        assert_eq!(tags, vec!["deploy", "prod"]);
+        assert_eq!(note, "Run it twice.\ntags: no tag");
+        assert_eq!(parse_annotation("tags:\n\n"), (vec![], String::new()));
+    }
+
+    #[test]
+    fn filter_by_tag() {
+        let conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        for cmd in ["make", "make deploy"] {
+            add_history(&conn, 1, cmd, "host", "/src", 1);
+        }
+        let mut sources = vec![Source {
+            schema: String::from("main"),
+            name: String::new(),
+            annotated: false,
+        }];
+        let filter = Filter {
+            tag: Some(String::from("prod")),
+            ..Default::default()
+        };
+        let query = |filter: &Filter, sources: &[Source]| -> Vec<History> {
+            let query = build_federated_query_string(
+                &Location::Everywhere,
+                Grouping::None,
+                filter,
+                sources,
+                &QueryContext::default(),
+            );
+            let mut stmt = conn.prepare(&query).unwrap();
+            let entries = stmt.query_map([], History::from_row).unwrap();
+            return entries.flatten().collect();
+        };
+        assert!(query(&filter, &sources).is_empty());
+        assert_eq!(query(&Filter::default(), &sources).len(), 2);
+
+        let deploy = insert_command(&conn, "make deploy").unwrap();
+        let tags = vec![String::from("deploy"), String::from("prod")];
+        save_annotation(&conn, deploy, &tags, "Needs the VPN").unwrap();
+        assert!(has_annotations(&conn, "main").unwrap());
+        sources[0].annotated = true;
+        let entries = query(&filter, &sources);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].cmd, "make deploy");
+        assert_eq!(entries[0].tags, tags);
+        assert_eq!(entries[0].note.as_deref(), Some("Needs the VPN"));
+        assert_eq!(query(&Filter::default(), &sources).len(), 2);
+
+        save_annotation(&conn, deploy, &[], "").unwrap();
+        assert!(query(&filter, &sources).is_empty());
+        assert!(query(&Filter::default(), &sources)[0].note.is_none());
+    }
+}
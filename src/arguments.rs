@@ -0,0 +1,31 @@
+/// Get the value given for an option, e.g. `--host myhost`
+pub fn get_option(args: &[String], name: &str) -> Option<String> {
+    let position = args.iter().position(|a| a == name)?;
+    return args.get(position + 1).map(|v| v.to_string());
+}
+
+/// Check if a flag, e.g. `--purge`, was given
+pub fn has_flag(args: &[String], name: &str) -> bool {
+    return args.iter().any(|a| a == name);
+}
+
+/// Get all arguments which are neither options nor values of `options_with_value`
+pub fn get_positional(args: &[String], options_with_value: &[&str]) -> Vec<String> {
+    let mut positional = Vec::new();
+    let mut skip_next = false;
+    for arg in args {
+        if skip_next {
+            skip_next = false;
+            continue;
+        }
+        if options_with_value.contains(&arg.as_str()) {
+            skip_next = true;
+            continue;
+        }
+        if arg.starts_with("--") {
+            continue;
+        }
+        positional.push(arg.to_string());
+    }
+    return positional;
+}
@@ -0,0 +1,87 @@
+use crate::environment::*;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const BASE64_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (value >> (18 - 6 * i)) & 0x3f;
+                encoded.push(BASE64_ALPHABET[index as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    return encoded;
+}
+
+/// The OSC 52 sequence setting the clipboard. In tmux it is passed through to the outer
+/// terminal, which needs `allow-passthrough` to be enabled.
+fn osc52(text: &str, in_tmux: bool) -> String {
+    let sequence = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+    if in_tmux {
+        return format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"));
+    }
+    return sequence;
+}
+
+/// Copy the text to the clipboard, by the terminal or by the configured command.
+/// The terminal escape also works over SSH, as it is handled by the local terminal.
+pub fn copy_to_clipboard(text: &str) -> Result<(), String> {
+    if let Some(command) = get_clipboard_command() {
+        let mut child = Command::new("sh")
+            .args(["-c", &command])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Cannot run {} {}", command, e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(text.as_bytes())
+                .map_err(|e| format!("Cannot copy to {} {}", command, e))?;
+        }
+        let status = child
+            .wait()
+            .map_err(|e| format!("Cannot run {} {}", command, e))?;
+        if !status.success() {
+            return Err(format!("{} failed", command));
+        }
+        return Ok(());
+    }
+    let mut tty = OpenOptions::new()
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|e| format!("Cannot copy to the clipboard {}", e))?;
+    tty.write_all(osc52(text, is_in_tmux()).as_bytes())
+        .and_then(|_| tty.flush())
+        .map_err(|e| format!("Cannot copy to the clipboard {}", e))?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_sequence() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"git log"), "Z2l0IGxvZw==");
+        assert_eq!(osc52("ls", false), "\x1b]52;c;bHM=\x07");
+        assert_eq!(osc52("ls", true), "\x1bPtmux;\x1b\x1b]52;c;bHM=\x07\x1b\\");
+    }
+}
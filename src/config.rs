@@ -0,0 +1,126 @@
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+
+/// Settings which do not fit into environment variables, read from
+/// `$HISTDB_SKIM_CONFIG` or `$XDG_CONFIG_HOME/zsh-histdb-skim/config.toml`
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub redact: RedactConfig,
+    /// Named searches, selectable with `--preset NAME` or alt-p in the picker
+    pub presets: BTreeMap<String, PresetConfig>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct PresetConfig {
+    pub location: Option<String>,
+    /// Only commands run in this directory or below, `~` is expanded
+    pub dir: Option<String>,
+    pub host: Option<String>,
+    pub exit_status: Option<i64>,
+    /// Epoch seconds or a duration ago, e.g. `30d`
+    pub since: Option<String>,
+    pub until: Option<String>,
+    /// Initial query of the picker
+    pub query: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RedactConfig {
+    /// Mask secrets in the list, the preview and exports
+    pub enabled: bool,
+    /// Use the built-in patterns in addition to `patterns`
+    pub builtin: bool,
+    /// Regular expressions, if they contain a group named `secret` only this group is masked
+    pub patterns: Vec<String>,
+}
+
+impl Default for RedactConfig {
+    fn default() -> Self {
+        RedactConfig {
+            enabled: true,
+            builtin: true,
+            patterns: Vec::new(),
+        }
+    }
+}
+
+/// The config and the error why the file was ignored
+static CONFIG: Lazy<(Config, Option<String>)> = Lazy::new(load_config);
+
+fn get_config_file() -> String {
+    if let Ok(file) = env::var("HISTDB_SKIM_CONFIG") {
+        return file;
+    }
+    let config_home = env::var("XDG_CONFIG_HOME")
+        .unwrap_or(format!("{}/.config", env::var("HOME").unwrap_or_default()));
+    return format!("{}/zsh-histdb-skim/config.toml", config_home);
+}
+
+fn load_config() -> (Config, Option<String>) {
+    let file = get_config_file();
+    let content = match fs::read_to_string(&file) {
+        Ok(content) => content,
+        Err(_) => return (Config::default(), None),
+    };
+    return match parse_config(&content) {
+        Ok(config) => (config, None),
+        Err(e) => (
+            Config::default(),
+            Some(format!("Ignoring invalid config {}: {}", file, e)),
+        ),
+    };
+}
+
+pub fn parse_config(content: &str) -> Result<Config, String> {
+    return toml::from_str(content).map_err(|e| e.to_string());
+}
+
+pub fn get_config() -> &'static Config {
+    return &CONFIG.0;
+}
+
+/// Why the config file was ignored, if it is invalid
+pub fn get_config_warning() -> Option<&'static String> {
+    return CONFIG.1.as_ref();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_for_missing_values() {
+        let config = parse_config("").unwrap();
+        assert!(config.redact.enabled);
+        assert!(config.redact.builtin);
+        let config =
+            parse_config("[redact]\nbuiltin = false\npatterns = ['foo(?P<secret>bar)']\n").unwrap();
+        assert!(config.redact.enabled);
+        assert!(!config.redact.builtin);
+        assert_eq!(config.redact.patterns, vec!["foo(?P<secret>bar)"]);
+        assert!(parse_config("[redact]\nenabled = 'yes'\n").is_err());
+    }
+
+    #[test]
+    fn presets() {
+        let config = parse_config(
+            "[presets.deploys]\nlocation = 'everywhere'\ndir = '~/infra'\nexit_status = 0\nsince = '30d'\nquery = 'kubectl'\n\n[presets.failed]\nexit_status = 1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.presets.keys().collect::<Vec<_>>(),
+            vec!["deploys", "failed"]
+        );
+        let deploys = &config.presets["deploys"];
+        assert_eq!(deploys.dir.as_deref(), Some("~/infra"));
+        assert_eq!(deploys.exit_status, Some(0));
+        assert!(config.presets["failed"].query.is_none());
+        assert!(parse_config("[presets.typo]\nexit = 0\n").is_err());
+    }
+}
@@ -0,0 +1,490 @@
+use crate::database::HistoryDatabase;
+use crate::environment::*;
+use crate::filter::Filter;
+use crate::grouping::Grouping;
+use crate::history::History;
+use crate::loader::LoadControl;
+use crate::location::Location;
+use crate::query::{build_federated_query_string, QueryContext};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::DirBuilder;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of result sets the daemon keeps, the least recently used one is dropped first
+const CACHE_SIZE: usize = 16;
+
+/// Number of entries the daemon keeps in all result sets together. Ungrouped results of
+/// everywhere may be the whole history, larger ones are not cached at all.
+const CACHE_ENTRIES: usize = 250_000;
+
+/// How often the daemon checks whether the databases changed, to refresh the cached results
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a client waits for the daemon to answer whether it serves its databases
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+const USAGE: &str = "Usage: daemon";
+
+/// A request is a single line of JSON, it is answered with one line per response
+#[derive(Serialize, Deserialize)]
+struct Request {
+    /// The databases of the client, the daemon must serve the same ones
+    databases: Vec<String>,
+    /// Without a load the client only checks whether the daemon serves its databases
+    load: Option<Load>,
+}
+
+/// The entries a client loads, the daemon builds the query from them itself
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Load {
+    pub location: Location,
+    pub grouping: Grouping,
+    pub filter: Filter,
+    pub context: QueryContext,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Response<T> {
+    Entry(T),
+    /// The daemon serves the databases of the client
+    Ready,
+    Error(String),
+}
+
+/// Get the user on the other end of the socket
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    return Ok(credentials.uid);
+}
+
+/// Get the user on the other end of the socket
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let mut uid = 0;
+    let mut gid = 0;
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    return Ok(uid);
+}
+
+/// Fail unless the other end of the socket is run by the same user, the history must neither
+/// be sent to nor be read from anybody else
+fn check_peer(stream: &UnixStream) -> std::io::Result<()> {
+    let uid = peer_uid(stream)?;
+    if uid != get_user_id() {
+        return Err(std::io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!("the socket belongs to the user {}", uid),
+        ));
+    }
+    return Ok(());
+}
+
+/// Create the directory of the socket, which only the user may access. An existing one must
+/// be a private directory of the user, otherwise others could replace the socket.
+fn create_socket_dir(socket: &str) -> Result<(), String> {
+    let dir = match Path::new(socket).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    match DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(format!("Cannot create {} {}", dir.display(), e)),
+    }
+    let metadata = std::fs::symlink_metadata(dir)
+        .map_err(|e| format!("Cannot create {} {}", dir.display(), e))?;
+    if !metadata.is_dir() || metadata.uid() != get_user_id() || metadata.mode() & 0o077 != 0 {
+        return Err(format!(
+            "{} must be a directory only you can access",
+            dir.display()
+        ));
+    }
+    return Ok(());
+}
+
+struct CachedResult {
+    used: Instant,
+    /// The data version of the databases the entries were loaded at
+    version: i64,
+    entries: Arc<Vec<History>>,
+}
+
+/// Keeps the databases open and the results of the recent queries, which are the locations,
+/// focuses and filters the picker was opened with
+struct Daemon {
+    database: HistoryDatabase,
+    databases: Vec<String>,
+    /// The results by their query. The queries run without holding the lock, so loading one
+    /// location does not wait for the others.
+    cache: Mutex<HashMap<String, CachedResult>>,
+}
+
+impl Daemon {
+    fn open(databases: Vec<String>) -> Result<Daemon, String> {
+        let database =
+            HistoryDatabase::open(&databases).map_err(|e| format!("Cannot open database {}", e))?;
+        return Ok(Daemon {
+            database,
+            databases,
+            cache: Mutex::new(HashMap::new()),
+        });
+    }
+
+    fn run_query(&self, query: &str) -> Result<Vec<History>, String> {
+        let conn = self.database.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare_cached(query)
+            .map_err(|e| format!("Cannot get result from database {}", e))?;
+        let entries = stmt
+            .query_map([], History::from_row)
+            .map_err(|e| format!("Cannot get result from database {}", e))?;
+        return Ok(entries.flatten().collect());
+    }
+
+    fn version(&self) -> Result<i64, String> {
+        return self
+            .database
+            .data_version()
+            .map_err(|e| format!("Cannot get result from database {}", e));
+    }
+
+    /// Run the cached queries again, which were run before the databases changed
+    fn refresh(&self) -> Result<(), String> {
+        let version = self.version()?;
+        let stale: Vec<String> = self
+            .cache
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, result)| result.version != version)
+            .map(|(query, _)| query.to_string())
+            .collect();
+        for query in stale {
+            let entries = self.run_query(&query);
+            let mut cache = self.cache.lock().unwrap();
+            match entries {
+                Ok(entries) if entries.len() <= CACHE_ENTRIES => {
+                    // a client may have loaded it at a newer version meanwhile
+                    if let Some(result) = cache.get_mut(&query).filter(|r| r.version < version) {
+                        result.version = version;
+                        result.entries = Arc::new(entries);
+                    }
+                }
+                _ => {
+                    cache.remove(&query);
+                }
+            }
+            Self::evict(&mut cache);
+        }
+        return Ok(());
+    }
+
+    /// Drop the least recently used results, until the cache is within its limits
+    fn evict(cache: &mut HashMap<String, CachedResult>) {
+        loop {
+            let entries: usize = cache.values().map(|result| result.entries.len()).sum();
+            if cache.len() <= CACHE_SIZE && entries <= CACHE_ENTRIES {
+                return;
+            }
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, result)| result.used)
+                .map(|(query, _)| query.to_string());
+            match oldest {
+                Some(oldest) => cache.remove(&oldest),
+                None => return,
+            };
+        }
+    }
+
+    /// Get the entries to load, the query is built with the current sources, as tags and
+    /// notes may have been added meanwhile
+    fn load(&self, load: &Load) -> Result<Arc<Vec<History>>, String> {
+        let sources = self
+            .database
+            .annotated_sources()
+            .map_err(|e| format!("Cannot get result from database {}", e))?;
+        let query = build_federated_query_string(
+            &load.location,
+            load.grouping,
+            &load.filter,
+            &sources,
+            &load.context,
+        );
+        return self.get(&query);
+    }
+
+    /// Get the entries of the query, from the cache if it has them for the current data
+    /// version of the databases. Otherwise the query is run and its result is cached.
+    fn get(&self, query: &str) -> Result<Arc<Vec<History>>, String> {
+        let version = self.version()?;
+        if let Some(result) = self.cache.lock().unwrap().get_mut(query) {
+            if result.version == version {
+                result.used = Instant::now();
+                return Ok(result.entries.clone());
+            }
+        }
+        let entries = Arc::new(self.run_query(query)?);
+        if entries.len() > CACHE_ENTRIES {
+            return Ok(entries);
+        }
+        let mut cache = self.cache.lock().unwrap();
+        // the refresher may have loaded it meanwhile
+        if !matches!(cache.get(query), Some(result) if result.version > version) {
+            cache.insert(
+                query.to_string(),
+                CachedResult {
+                    used: Instant::now(),
+                    version,
+                    entries: entries.clone(),
+                },
+            );
+            Self::evict(&mut cache);
+        }
+        return Ok(entries);
+    }
+
+    /// Answer the request of a client, it stops as soon as the client disconnects
+    fn serve(&self, stream: UnixStream) -> std::io::Result<()> {
+        check_peer(&stream)?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let mut writer = BufWriter::new(&stream);
+        let mut respond = |response: Response<&History>| -> std::io::Result<()> {
+            serde_json::to_writer(&mut writer, &response)?;
+            return writer.write_all(b"\n");
+        };
+        let request: Request = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => return respond(Response::Error(format!("Invalid request {}", e))),
+        };
+        if request.databases != self.databases {
+            return respond(Response::Error(format!(
+                "The daemon serves {}",
+                self.databases.join(":")
+            )));
+        }
+        match request.load {
+            Some(load) => match self.load(&load) {
+                Ok(entries) => {
+                    for entry in entries.iter() {
+                        respond(Response::Entry(entry))?;
+                    }
+                }
+                Err(e) => respond(Response::Error(e))?,
+            },
+            None => respond(Response::Ready)?,
+        }
+        return writer.flush();
+    }
+}
+
+/// Loads the entries from the daemon, instead of opening the databases
+pub struct DaemonClient {
+    socket: String,
+    databases: Vec<String>,
+}
+
+impl DaemonClient {
+    /// Connect to the daemon, if it is running and serves the databases
+    pub fn connect(socket: &str, databases: &[String]) -> Option<DaemonClient> {
+        let client = DaemonClient {
+            socket: socket.to_string(),
+            databases: databases.to_vec(),
+        };
+        client.ping().ok()?;
+        return Some(client);
+    }
+
+    /// Check whether the daemon serves the databases
+    fn ping(&self) -> Result<(), String> {
+        let stream = self
+            .request(None)
+            .map_err(|e| format!("Cannot reach the daemon {}", e))?;
+        stream
+            .set_read_timeout(Some(CONNECT_TIMEOUT))
+            .map_err(|e| format!("Cannot reach the daemon {}", e))?;
+        let mut line = String::new();
+        BufReader::new(stream)
+            .read_line(&mut line)
+            .map_err(|e| format!("Cannot get result from the daemon {}", e))?;
+        return match serde_json::from_str::<Response<History>>(&line) {
+            Ok(Response::Ready) => Ok(()),
+            Ok(Response::Error(e)) => Err(e),
+            _ => Err(String::from("Invalid response of the daemon")),
+        };
+    }
+
+    fn request(&self, load: Option<&Load>) -> std::io::Result<UnixStream> {
+        let mut stream = UnixStream::connect(&self.socket)?;
+        check_peer(&stream)?;
+        let request = Request {
+            databases: self.databases.clone(),
+            load: load.cloned(),
+        };
+        serde_json::to_writer(&mut stream, &request)?;
+        stream.write_all(b"\n")?;
+        return Ok(stream);
+    }
+
+    /// Get the entries to load and pass them to `send` until it returns false.
+    /// Cancelling the `control` disconnects from the daemon.
+    pub fn load(
+        &self,
+        load: &Load,
+        control: &LoadControl,
+        mut send: impl FnMut(History) -> bool,
+    ) -> Result<(), String> {
+        let stream = self
+            .request(Some(load))
+            .map_err(|e| format!("Cannot reach the daemon {}", e))?;
+        if let Ok(connection) = stream.try_clone() {
+            control.set_connection(connection);
+        }
+        for line in BufReader::new(stream).lines() {
+            if control.is_cancelled() {
+                break;
+            }
+            let line = line.map_err(|e| format!("Cannot get result from the daemon {}", e))?;
+            match serde_json::from_str::<Response<History>>(&line) {
+                Ok(Response::Entry(mut entry)) => {
+                    entry.update_display();
+                    if !send(entry) {
+                        break;
+                    }
+                }
+                Ok(Response::Error(e)) => return Err(e),
+                Ok(Response::Ready) => return Err(String::from("Invalid response of the daemon")),
+                Err(e) => return Err(format!("Invalid response of the daemon {}", e)),
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Listen on the socket until the daemon is killed, every client is served by a thread
+fn listen(listener: UnixListener, daemon: Arc<Daemon>) {
+    let refresher = daemon.clone();
+    thread::spawn(move || loop {
+        thread::sleep(REFRESH_INTERVAL);
+        let _ = refresher.refresh();
+    });
+    for stream in listener.incoming().flatten() {
+        let daemon = daemon.clone();
+        thread::spawn(move || {
+            let _ = daemon.serve(stream);
+        });
+    }
+}
+
+/// `daemon` keeps the databases of HISTDB_FILE open and serves the picker through the socket
+pub fn run(args: &[String]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    let socket = get_daemon_socket();
+    if UnixStream::connect(&socket).is_ok() {
+        return Err(format!("A daemon is already listening on {}", socket));
+    }
+    let daemon = Arc::new(Daemon::open(get_histdb_databases())?);
+    for warning in daemon.database.check_indexes() {
+        eprintln!("{}", warning);
+    }
+    create_socket_dir(&socket)?;
+    // a socket left over by a daemon which did not exit cleanly
+    let _ = std::fs::remove_file(&socket);
+    let listener =
+        UnixListener::bind(&socket).map_err(|e| format!("Cannot listen on {} {}", socket, e))?;
+    listen(listener, daemon);
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::open_writable;
+    use crate::record::record_start;
+    use std::env::temp_dir;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn serves_cached_and_changed_entries() {
+        let dir = temp_dir().join(format!("histdb-skim-daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let database = dir.join("history.db").to_string_lossy().to_string();
+        let socket = dir.join("daemon.sock").to_string_lossy().to_string();
+        let mut conn = open_writable(&database).unwrap();
+        record_start(&mut conn, "make", 1, "host", "/src", 10).unwrap();
+
+        let databases = vec![database];
+        let daemon = Arc::new(Daemon::open(databases.clone()).unwrap());
+        let listener = UnixListener::bind(&socket).unwrap();
+        thread::spawn(move || listen(listener, daemon));
+
+        let commands = |client: &DaemonClient| -> Vec<String> {
+            let load = Load {
+                location: Location::Everywhere,
+                grouping: Grouping::None,
+                filter: Filter::default(),
+                context: QueryContext::default(),
+            };
+            let mut commands = Vec::new();
+            client
+                .load(&load, &LoadControl::default(), |entry| {
+                    commands.push(entry.cmd);
+                    true
+                })
+                .unwrap();
+            return commands;
+        };
+        assert!(DaemonClient::connect(&socket, &["other.db".to_string()]).is_none());
+        let client = DaemonClient::connect(&socket, &databases).unwrap();
+        assert_eq!(commands(&client), vec!["make"]);
+
+        record_start(&mut conn, "make test", 1, "host", "/src", 20).unwrap();
+        assert_eq!(commands(&client), vec!["make test", "make"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn socket_dir_is_private() {
+        let dir = temp_dir().join(format!("histdb-skim-socket-{}", std::process::id()));
+        let socket = dir.join("daemon.sock").to_string_lossy().to_string();
+        create_socket_dir(&socket).unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        create_socket_dir(&socket).unwrap();
+
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(create_socket_dir(&socket).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
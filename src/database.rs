@@ -0,0 +1,362 @@
+use rusqlite::{params, Connection, InterruptHandle, OpenFlags, Result};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Indexes zsh-histdb creates, without them loading the history gets slow
+pub const RECOMMENDED_INDEXES: &[&str] = &["hist_time", "place_dir", "history_command_place"];
+
+/// A database the history is read from, attached to the connection as `schema`
+#[derive(Clone)]
+pub struct Source {
+    pub schema: String,
+    pub name: String,
+    /// Whether the database contains the tables of the tags and notes
+    pub annotated: bool,
+}
+
+/// The schema zsh-histdb creates (see tests/create_test_db.sh)
+const SCHEMA: &str = "
+create table commands (id integer primary key autoincrement, argv text, unique(argv) on conflict ignore);
+create table places   (id integer primary key autoincrement, host text, dir text, unique(host, dir) on conflict ignore);
+create table history  (id integer primary key autoincrement,
+                       session int,
+                       command_id int references commands (id),
+                       place_id int references places (id),
+                       exit_status int,
+                       start_time int,
+                       duration int);
+PRAGMA user_version = 2;
+create index if not exists hist_time on history(start_time);
+create index if not exists place_dir on places(dir);
+create index if not exists place_host on places(host);
+create index if not exists history_command_place on history(command_id, place_id);
+";
+
+/// Tags and notes of the commands. They are not part of the histdb schema, so the tables are
+/// only created when the first command is annotated.
+const ANNOTATION_SCHEMA: &str = "
+create table if not exists command_tags  (command_id int references commands (id), tag text, unique(command_id, tag) on conflict ignore);
+create table if not exists command_notes (command_id integer primary key references commands (id), note text);
+create index if not exists command_tags_tag on command_tags(tag);
+";
+
+/// The history entries imported without a start time. Their start time is made up, so on the
+/// next import of the file they are recognized by their order instead.
+const IMPORT_SCHEMA: &str = "
+create table if not exists untimed_imports (history_id integer primary key references history (id));
+";
+
+/// Open the histdb database for writing, the tables are created if they do not exist yet
+pub fn open_writable(path: &str) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(Duration::from_millis(1000))?;
+    ensure_schema(&conn)?;
+    return Ok(conn);
+}
+
+/// Open the histdb database to record commands. The shell waits for it, so it waits a while
+/// for other writers, instead of losing the entry. With `wal` the database is switched to WAL
+/// to not block readers, this stays for all its users and needs a local filesystem.
+pub fn open_recorder(path: &str, wal: bool) -> Result<Connection> {
+    if let Some(dir) = Path::new(path).parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let conn = Connection::open(path)?;
+    conn.busy_timeout(Duration::from_millis(5000))?;
+    if wal {
+        conn.query_row("pragma journal_mode = wal", [], |_| Ok(()))?;
+        conn.execute_batch("pragma synchronous = normal")?;
+    }
+    ensure_schema(&conn)?;
+    return Ok(conn);
+}
+
+/// Create the histdb tables, if the database does not contain them
+pub fn ensure_schema(conn: &Connection) -> Result<()> {
+    let tables: i64 = conn.query_row(
+        "select count() from sqlite_master where type = 'table' and name = 'history'",
+        [],
+        |row| row.get(0),
+    )?;
+    if tables == 0 {
+        conn.execute_batch(SCHEMA)?;
+    }
+    return Ok(());
+}
+
+/// Create the tables of the tags and notes, if the database does not contain them
+pub fn ensure_annotation_schema(conn: &Connection) -> Result<()> {
+    return conn.execute_batch(ANNOTATION_SCHEMA);
+}
+
+/// Create the table of the commands imported without start time, if it does not exist
+pub fn ensure_import_schema(conn: &Connection) -> Result<()> {
+    return conn.execute_batch(IMPORT_SCHEMA);
+}
+
+/// Whether the tables of the tags and notes exist in the schema
+pub fn has_annotations(conn: &Connection, schema: &str) -> Result<bool> {
+    let tables: i64 = conn.query_row(
+        &format!(
+            "select count() from {}.sqlite_master where type = 'table' and name in ('command_tags', 'command_notes')",
+            schema
+        ),
+        [],
+        |row| row.get(0),
+    )?;
+    return Ok(tables == 2);
+}
+
+/// Get the id of the command, it is inserted if it is not known yet
+pub fn insert_command(conn: &Connection, argv: &str) -> Result<i64> {
+    conn.execute("insert into commands (argv) values (?1)", params![argv])?;
+    return conn.query_row(
+        "select id from commands where argv = ?1",
+        params![argv],
+        |row| row.get(0),
+    );
+}
+
+/// Get the id of the place, it is inserted if it is not known yet
+pub fn insert_place(conn: &Connection, host: &str, dir: &str) -> Result<i64> {
+    conn.execute(
+        "insert into places (host, dir) values (?1, ?2)",
+        params![host, dir],
+    )?;
+    return conn.query_row(
+        "select id from places where host = ?1 and dir = ?2",
+        params![host, dir],
+        |row| row.get(0),
+    );
+}
+
+/// Insert a history entry into a test database, returns its id
+#[cfg(test)]
+pub fn add_history(
+    conn: &Connection,
+    session: i64,
+    cmd: &str,
+    host: &str,
+    dir: &str,
+    start_time: i64,
+) -> i64 {
+    let command_id = insert_command(conn, cmd).unwrap();
+    let place_id = insert_place(conn, host, dir).unwrap();
+    conn.execute(
+        "insert into history (session, command_id, place_id, start_time) values (?1, ?2, ?3, ?4)",
+        params![session, command_id, place_id, start_time],
+    )
+    .unwrap();
+    return conn.last_insert_rowid();
+}
+
+/// Get a session id which has not been used so far
+pub fn next_session_id(conn: &Connection) -> Result<i64> {
+    return conn.query_row(
+        "select coalesce(max(session), 0) + 1 from history",
+        [],
+        |row| row.get(0),
+    );
+}
+
+/// The name a database is shown with, which is its file name without extension
+fn source_name(path: &str) -> String {
+    return Path::new(path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or(path.to_string());
+}
+
+/// The sources the databases are attached as, the first one is the main database.
+/// The name identifies the source, so databases with the same file name get a suffix, e.g.
+/// `zsh-history`, `zsh-history-2`.
+pub fn get_sources(paths: &[String]) -> Vec<Source> {
+    let mut names: Vec<String> = Vec::new();
+    for path in paths {
+        let stem = source_name(path);
+        let mut name = stem.clone();
+        let mut number = 1;
+        while names.contains(&name) {
+            number += 1;
+            name = format!("{}-{}", stem, number);
+        }
+        names.push(name);
+    }
+    return names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| Source {
+            schema: match i {
+                0 => String::from("main"),
+                _ => format!("source{}", i),
+            },
+            name: match paths.len() {
+                1 => String::from(""),
+                _ => name,
+            },
+            annotated: false,
+        })
+        .collect();
+}
+
+/// Attach the additional databases to the connection of the first one.
+/// They are read only, as long as the connection was opened read only.
+pub fn attach_sources(conn: &Connection, paths: &[String]) -> Result<Vec<Source>> {
+    let sources = get_sources(paths);
+    for (path, source) in paths.iter().zip(&sources).skip(1) {
+        conn.execute("attach database ?1 as ?2", params![path, source.schema])?;
+    }
+    return Ok(sources);
+}
+
+/// The read only connection used as long as the picker is open, it is shared by all loads
+/// so prepared statements can be reused when switching locations
+pub struct HistoryDatabase {
+    pub conn: Mutex<Connection>,
+    pub sources: Vec<Source>,
+    interrupt: Arc<InterruptHandle>,
+}
+
+impl HistoryDatabase {
+    /// Open the first database and attach the others
+    pub fn open(paths: &[String]) -> Result<HistoryDatabase> {
+        let conn = Connection::open_with_flags(&paths[0], OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let sources = attach_sources(&conn, paths)?;
+        let interrupt = Arc::new(conn.get_interrupt_handle());
+        return Ok(HistoryDatabase {
+            conn: Mutex::new(conn),
+            sources,
+            interrupt,
+        });
+    }
+
+    pub fn interrupt_handle(&self) -> Arc<InterruptHandle> {
+        return self.interrupt.clone();
+    }
+
+    /// Get the sources, with whether they contain tags and notes by now
+    pub fn annotated_sources(&self) -> Result<Vec<Source>> {
+        let conn = self.conn.lock().unwrap();
+        let mut sources = self.sources.clone();
+        for source in sources.iter_mut() {
+            source.annotated = has_annotations(&conn, &source.schema)?;
+        }
+        return Ok(sources);
+    }
+
+    /// Changes whenever another connection modified one of the sources
+    pub fn data_version(&self) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        let mut version = 0;
+        for source in &self.sources {
+            let source_version: i64 = conn.query_row(
+                &format!("pragma {}.data_version", source.schema),
+                [],
+                |row| row.get(0),
+            )?;
+            version += source_version;
+        }
+        return Ok(version);
+    }
+
+    /// Get a warning for every source which lacks one of the recommended indexes
+    pub fn check_indexes(&self) -> Vec<String> {
+        let conn = self.conn.lock().unwrap();
+        let mut warnings = Vec::new();
+        for source in &self.sources {
+            let missing = match get_missing_indexes(&conn, &source.schema) {
+                Ok(missing) => missing,
+                Err(_) => continue,
+            };
+            if !missing.is_empty() {
+                let database = match source.name.is_empty() {
+                    true => String::from("The database"),
+                    false => format!("Database {}", source.name),
+                };
+                warnings.push(format!(
+                    "{} is missing the indexes {}, loading the history will be slow",
+                    database,
+                    missing.join(", ")
+                ));
+            }
+        }
+        return warnings;
+    }
+}
+
+/// Get the recommended indexes which do not exist in the schema
+pub fn get_missing_indexes(conn: &Connection, schema: &str) -> Result<Vec<&'static str>> {
+    let mut stmt = conn.prepare(&format!(
+        "select name from {}.sqlite_master where type = 'index'",
+        schema
+    ))?;
+    let existing = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>>>()?;
+    return Ok(RECOMMENDED_INDEXES
+        .iter()
+        .filter(|i| !existing.iter().any(|e| e == *i))
+        .copied()
+        .collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_indexes() {
+        let conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        assert!(get_missing_indexes(&conn, "main").unwrap().is_empty());
+        conn.execute("drop index place_dir", []).unwrap();
+        assert_eq!(
+            get_missing_indexes(&conn, "main").unwrap(),
+            vec!["place_dir"]
+        );
+    }
+
+    #[test]
+    fn wal_is_opt_in() {
+        let dir = std::env::temp_dir().join(format!("histdb-skim-wal-{}", std::process::id()));
+        let path = dir.join("history.db").to_string_lossy().to_string();
+        let journal_mode = |conn: &Connection| -> String {
+            return conn
+                .query_row("pragma journal_mode", [], |row| row.get(0))
+                .unwrap();
+        };
+        assert_eq!(
+            journal_mode(&open_recorder(&path, false).unwrap()),
+            "delete"
+        );
+        assert_eq!(journal_mode(&open_recorder(&path, true).unwrap()), "wal");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn source_names_are_unique() {
+        let paths = [
+            "/containers/a/zsh-history.db",
+            "/containers/b/zsh-history.db",
+            "/home/me/zsh-history-2.db",
+        ]
+        .map(String::from);
+        let names: Vec<String> = get_sources(&paths).into_iter().map(|s| s.name).collect();
+        assert_eq!(
+            names,
+            vec!["zsh-history", "zsh-history-2", "zsh-history-2-2"]
+        );
+        assert_eq!(get_sources(&paths[..1])[0].name, "");
+    }
+
+    #[test]
+    fn annotation_tables_are_created_lazily() {
+        let conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        assert!(!has_annotations(&conn, "main").unwrap());
+        ensure_annotation_schema(&conn).unwrap();
+        ensure_annotation_schema(&conn).unwrap();
+        assert!(has_annotations(&conn, "main").unwrap());
+    }
+}
@@ -1,5 +1,8 @@
-use crate::location::Location;
+use crate::config::get_config;
+use crate::location::{parse_location, Location};
+use crate::theme::theme;
 use std::env;
+use std::process::Command;
 
 /// Get the default (which is non us! or the us date format)
 /// - [ ] Read from locale to determine default
@@ -14,11 +17,31 @@ pub fn get_date_format() -> String {
     }
 }
 
-/// Get the histdb file from the environment
+/// Get the histdb file from the environment, this is the first one if several are given
 pub fn get_histdb_database() -> String {
+    return get_histdb_databases()
+        .first()
+        .map(|f| f.to_string())
+        .unwrap_or_default();
+}
+
+/// Get all histdb files from the environment, HISTDB_FILE may list several separated by `:`.
+/// Without it the default file of zsh-histdb is used, e.g. if started from bash.
+pub fn get_histdb_databases() -> Vec<String> {
     let key = "HISTDB_FILE";
-    let db_file = env::var(key).unwrap_or(String::from(""));
-    return db_file.to_string();
+    let db_files = env::var(key).unwrap_or(String::from(""));
+    let mut files: Vec<String> = db_files
+        .split(':')
+        .filter(|f| !f.is_empty())
+        .map(|f| f.to_string())
+        .collect();
+    if files.is_empty() {
+        files.push(format!(
+            "{}/.histdb/zsh-history.db",
+            env::var("HOME").unwrap_or_default()
+        ));
+    }
+    return files;
 }
 
 /// Get the histdb session from the environment
@@ -35,15 +58,28 @@ pub fn get_current_dir() -> String {
     return cdir_string.to_string();
 }
 
-/// Get the current histdb host from the environment
+/// Get the current histdb host from the environment, falling back to the hostname
 pub fn get_current_host() -> String {
     let mut host = env::var("HISTDB_HOST").unwrap_or(String::from(""));
     if host.starts_with("'") && host.ends_with("'") {
         host = host[1..host.len() - 1].to_string()
     }
+    if host.is_empty() {
+        host = get_hostname();
+    }
     return host.to_string();
 }
 
+fn get_hostname() -> String {
+    if let Ok(host) = env::var("HOSTNAME") {
+        return host;
+    }
+    return Command::new("hostname")
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_default();
+}
+
 /// Disable sorting so history would be kept in historical order
 pub fn get_nosort_option() -> bool {
     let nosort = env::var("HISTDB_NOSORT").unwrap_or(String::from("false"));
@@ -53,28 +89,125 @@ pub fn get_nosort_option() -> bool {
     return false;
 }
 
+/// Keep location, grouping and focus for the next invocation in the same session
+pub fn get_persist_state_option() -> bool {
+    let persist = env::var("HISTDB_SKIM_PERSIST_STATE").unwrap_or(String::from("false"));
+    return persist.to_lowercase() == "true" || persist == "1";
+}
+
+/// Get the id of the user running this process
+pub fn get_user_id() -> u32 {
+    return unsafe { libc::getuid() };
+}
+
+/// Get the socket of the daemon, by default in a directory of the user in the runtime directory
+pub fn get_daemon_socket() -> String {
+    if let Ok(socket) = env::var("HISTDB_SKIM_SOCKET") {
+        return socket;
+    }
+    return match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if !dir.is_empty() => format!("{}/zsh-histdb-skim/daemon.sock", dir),
+        _ => format!("/tmp/zsh-histdb-skim-{}/daemon.sock", get_user_id()),
+    };
+}
+
+/// Open the picker in a tmux popup of the given size, WIDTH[,HEIGHT] or true for the default
+pub fn get_tmux_popup_option() -> String {
+    return env::var("HISTDB_SKIM_TMUX_POPUP").unwrap_or_default();
+}
+
+/// Command the copied text is piped to, instead of the clipboard of the terminal
+pub fn get_clipboard_command() -> Option<String> {
+    return env::var("HISTDB_SKIM_CLIPBOARD_COMMAND")
+        .ok()
+        .filter(|c| !c.is_empty());
+}
+
+/// The editor the tags and notes are edited with
+pub fn get_editor() -> String {
+    for name in ["VISUAL", "EDITOR"] {
+        if let Some(editor) = env::var(name).ok().filter(|e| !e.is_empty()) {
+            return editor;
+        }
+    }
+    return String::from("vi");
+}
+
+/// Whether we are running inside of tmux
+pub fn is_in_tmux() -> bool {
+    return !env::var("TMUX").unwrap_or_default().is_empty();
+}
+
 /// Configure preview window option [POSITION][:SIZE[%]][:wrap][:hidden][:+SCROLL[-OFFSET]]
 pub fn get_preview_window_option() -> String {
     let window = env::var("HISTDB_PREVIEW").unwrap_or(String::from("right:50%:true:false"));
     return window.to_string();
 }
 
+/// Height of the picker, in lines or percent of the terminal. Below 100% it is shown inline.
+pub fn get_height_option() -> String {
+    return env::var("HISTDB_HEIGHT").unwrap_or(String::from("100%"));
+}
+
+/// Layout of the picker: reverse (prompt at the top), default or reverse-list
+pub fn get_layout_option() -> String {
+    let layout = env::var("HISTDB_LAYOUT").unwrap_or(String::from("reverse"));
+    return match layout.to_lowercase().as_str() {
+        "default" => String::from("default"),
+        "reverse-list" => String::from("reverse-list"),
+        _ => String::from("reverse"),
+    };
+}
+
+/// Switch the database to WAL when recording, it is not done by default as it persists and
+/// does not work on network filesystems
+pub fn get_wal_option() -> bool {
+    let wal = env::var("HISTDB_SKIM_WAL").unwrap_or(String::from("false"));
+    return wal.to_lowercase() == "true" || wal == "1";
+}
+
+/// Draw the locations as boxed tabs, otherwise they are shown in a single line
+pub fn get_border_option() -> bool {
+    let border = env::var("HISTDB_BORDER").unwrap_or(String::from("true"));
+    return border.to_lowercase() != "false" && border != "0";
+}
+
+pub fn get_prompt_option() -> String {
+    return env::var("HISTDB_PROMPT").unwrap_or(String::from("history >>"));
+}
+
+/// Margin around the picker TOP,RIGHT,BOTTOM,LEFT, in lines/columns or percent
+pub fn get_margin_option() -> String {
+    return env::var("HISTDB_MARGIN").unwrap_or(String::from("0,0,0,0"));
+}
+
 /// Configure preview window option [POSITION][:SIZE[%]][:wrap][:hidden][:+SCROLL[-OFFSET]]
 pub fn get_default_tab() -> Location {
-    let default_tab = env::var("HISTDB_DEFAULT_TAB")
-        .unwrap_or(String::from("Session"))
-        .to_lowercase();
-    match default_tab.as_str() {
-        "directory" => Location::Directory,
-        "machine" => Location::Machine,
-        "host" => Location::Machine,
-        "everywhere" => Location::Everywhere,
-        "*" => Location::Everywhere,
-        _ => Location::Session,
-    }
+    let default_tab = env::var("HISTDB_DEFAULT_TAB").unwrap_or(String::from("Session"));
+    return parse_location(&default_tab);
 }
 
+/// The color option of skim, defaults to the one of the theme
 pub fn get_color() -> String {
-    let color = env::var("HISTDB_COLOR").unwrap_or(String::from("auto"));
+    let color = env::var("HISTDB_COLOR").unwrap_or(theme().skim.to_string());
     return color.to_string();
 }
+
+/// The name of the theme, without HISTDB_THEME colors are disabled if NO_COLOR is set
+pub fn get_theme_option() -> String {
+    if let Ok(theme) = env::var("HISTDB_THEME") {
+        return theme;
+    }
+    if !env::var("NO_COLOR").unwrap_or_default().is_empty() {
+        return String::from("no-color");
+    }
+    return String::from("dark");
+}
+
+/// Mask secrets in commands, HISTDB_REDACT overrides the `redact.enabled` config
+pub fn get_redact_option() -> bool {
+    return match env::var("HISTDB_REDACT") {
+        Ok(v) => v.to_lowercase() == "true" || v == "1",
+        Err(_) => get_config().redact.enabled,
+    };
+}
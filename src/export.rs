@@ -0,0 +1,214 @@
+use crate::arguments::*;
+use crate::config::get_config_warning;
+use crate::environment::*;
+use crate::filter::{parse_time, Filter};
+use crate::grouping::Grouping;
+use crate::history::History;
+use crate::location::{parse_location, Location};
+use crate::query::build_filtered_query_string;
+use crate::redact::{get_redact_warnings, redact_for_export};
+use rusqlite::{Connection, OpenFlags};
+use serde_json::json;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Write};
+
+const CSV_HEADER: &str = "id,argv,start_time,duration,exit_status,session,host,dir";
+
+fn format_jsonl(entry: &History, argv: &str) -> String {
+    return json!({
+        "id": entry.id,
+        "argv": argv,
+        "start_time": entry.start,
+        "duration": entry.duration,
+        "exit_status": entry.exit_status,
+        "session": entry.session,
+        "host": entry.host,
+        "dir": entry.dir,
+    })
+    .to_string();
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", value.replace('"', "\"\""));
+    }
+    return value.to_string();
+}
+
+fn format_csv(entry: &History, argv: &str) -> String {
+    let optional = |v: Option<i64>| v.map(|v| v.to_string()).unwrap_or_default();
+    return [
+        entry.id.to_string(),
+        csv_field(argv),
+        entry.start.to_string(),
+        optional(entry.duration),
+        optional(entry.exit_status),
+        entry.session.to_string(),
+        csv_field(&entry.host),
+        csv_field(&entry.dir),
+    ]
+    .join(",");
+}
+
+/// Write all entries of the location matching the filter, as JSON Lines or CSV.
+/// Secrets in the commands are masked, if `redact` is set.
+pub fn export_entries(
+    conn: &Connection,
+    location: &Location,
+    filter: &Filter,
+    format: &str,
+    redact: bool,
+    out: &mut dyn Write,
+) -> Result<usize, String> {
+    let formatter: fn(&History, &str) -> String = match format {
+        "jsonl" | "json" => format_jsonl,
+        "csv" => format_csv,
+        _ => return Err(format!("Unknown format {}, use jsonl or csv", format)),
+    };
+    let query = build_filtered_query_string(location, Grouping::None, filter);
+    let mut stmt = conn.prepare(&query).map_err(|e| e.to_string())?;
+    let entries = stmt
+        .query_map([], History::from_row)
+        .map_err(|e| e.to_string())?;
+
+    if format == "csv" {
+        writeln!(out, "{}", CSV_HEADER).map_err(|e| e.to_string())?;
+    }
+    let mut count = 0;
+    for entry in entries.flatten() {
+        let argv = match redact {
+            true => redact_for_export(&entry.cmd),
+            false => Cow::Borrowed(entry.cmd.as_str()),
+        };
+        writeln!(out, "{}", formatter(&entry, &argv)).map_err(|e| e.to_string())?;
+        count += 1;
+    }
+    return Ok(count);
+}
+
+/// `export [--format jsonl|csv] [--location LOCATION] [--since TIME] [--until TIME] [--host HOST] [--output FILE] [--no-redact]`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let parse = |name: &str| -> Result<Option<i64>, String> {
+        return match get_option(args, name) {
+            Some(v) => parse_time(&v)
+                .map(Some)
+                .ok_or(format!("Invalid time for {}: {}", name, v)),
+            None => Ok(None),
+        };
+    };
+    let filter = Filter {
+        since: parse("--since")?,
+        until: parse("--until")?,
+        host: get_option(args, "--host"),
+        ..Default::default()
+    };
+    let location = parse_location(&get_option(args, "--location").unwrap_or(String::from("*")));
+    let format = get_option(args, "--format").unwrap_or(String::from("jsonl"));
+
+    let conn = Connection::open_with_flags(get_histdb_database(), OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Cannot open database: {}", e))?;
+    let mut out: Box<dyn Write> = match get_option(args, "--output") {
+        Some(file) => Box::new(File::create(&file).map_err(|e| format!("{}: {}", file, e))?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let redact = !has_flag(args, "--no-redact");
+    if redact {
+        for warning in get_config_warning()
+            .into_iter()
+            .cloned()
+            .chain(get_redact_warnings())
+        {
+            eprintln!("{}", warning);
+        }
+    }
+    export_entries(&conn, &location, &filter, &format, redact, &mut out)?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::*;
+
+    fn test_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let id = add_history(&conn, 1, "echo \"a, b\"", "testhost", "/tmp", 1640995200);
+        crate::record::record_finish(&conn, id, 0, 1640995203).unwrap();
+        add_history(&conn, 2, "echo \"a, b\"", "otherhost", "/home", 1640995300);
+        conn
+    }
+
+    #[test]
+    fn export_csv() {
+        let mut out: Vec<u8> = Vec::new();
+        let count = export_entries(
+            &test_db(),
+            &Location::Everywhere,
+            &Filter::default(),
+            "csv",
+            false,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(count, 2);
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            "2,\"echo \"\"a, b\"\"\",1640995300,,,2,otherhost,/home"
+        );
+        assert_eq!(
+            lines[2],
+            "1,\"echo \"\"a, b\"\"\",1640995200,3,0,1,testhost,/tmp"
+        );
+    }
+
+    #[test]
+    fn export_jsonl_filtered() {
+        let mut out: Vec<u8> = Vec::new();
+        let filter = Filter {
+            since: None,
+            until: Some(1640995250),
+            host: Some(String::from("testhost")),
+            ..Default::default()
+        };
+        let count = export_entries(
+            &test_db(),
+            &Location::Everywhere,
+            &filter,
+            "jsonl",
+            false,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        let row: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(row["argv"], "echo \"a, b\"");
+        assert_eq!(row["host"], "testhost");
+        assert_eq!(row["exit_status"], 0);
+    }
+
+    #[test]
+    fn export_redacts_secrets() {
+        let conn = test_db();
+        add_history(
+            &conn,
+            3,
+            "curl -H 'Authorization: Bearer abc'",
+            "testhost",
+            "/tmp",
+            1640995400,
+        );
+        let filter = Filter {
+            since: Some(1640995400),
+            ..Default::default()
+        };
+        let mut out: Vec<u8> = Vec::new();
+        export_entries(&conn, &Location::Everywhere, &filter, "csv", true, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("Bearer ***'"));
+    }
+}
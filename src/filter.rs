@@ -0,0 +1,169 @@
+use humantime::parse_duration;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::env;
+use std::time::SystemTime;
+
+/// The operators of comparisons, they become part of the query
+const OPERATORS: &[&str] = &["==", "!=", ">=", "<=", ">", "<"];
+
+/// A comparison of a numeric column, e.g. `!= 0` or `> 60`
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+pub struct Comparison {
+    pub operator: &'static str,
+    pub value: i64,
+}
+
+/// Only the known operators are accepted, e.g. from a client of the daemon
+impl<'de> Deserialize<'de> for Comparison {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Comparison, D::Error> {
+        #[derive(Deserialize)]
+        struct Given {
+            operator: String,
+            value: i64,
+        }
+        let given = Given::deserialize(deserializer)?;
+        let operator = OPERATORS
+            .iter()
+            .find(|o| **o == given.operator)
+            .ok_or_else(|| D::Error::custom(format!("Invalid operator {}", given.operator)))?;
+        return Ok(Comparison {
+            operator,
+            value: given.value,
+        });
+    }
+}
+
+impl Comparison {
+    pub fn equal(value: i64) -> Comparison {
+        return Comparison {
+            operator: "==",
+            value,
+        };
+    }
+}
+
+/// Restrictions of the history entries, which are applied on top of the location
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Filter {
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub host: Option<String>,
+    /// Only entries run in this directory or below
+    pub dir: Option<String>,
+    /// Only entries run in a directory containing this text
+    pub dir_contains: Option<String>,
+    pub exit_status: Option<Comparison>,
+    /// Duration in seconds
+    pub duration: Option<Comparison>,
+    /// Only entries among the given number of most recent ones, grouped entries count all
+    /// their executions though
+    pub recent: Option<usize>,
+    /// Only entries of commands tagged with this tag
+    pub tag: Option<String>,
+}
+
+/// Parse a point in time, either as epoch seconds or as a duration ago, e.g. `2d` or `3h`
+pub fn parse_time(value: &str) -> Option<i64> {
+    if let Ok(epoch) = value.parse::<i64>() {
+        return Some(epoch);
+    }
+    let ago = parse_duration(value).ok()?;
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    return Some(now.saturating_sub(ago).as_secs() as i64);
+}
+
+/// Parse a duration, either as seconds or e.g. `90s` or `5m`
+pub fn parse_seconds(value: &str) -> Option<i64> {
+    if let Ok(seconds) = value.parse::<i64>() {
+        return Some(seconds);
+    }
+    return parse_duration(value).ok().map(|d| d.as_secs() as i64);
+}
+
+/// Parse a comparison like `!0`, `>60s` or `<=5`, without an operator it is an equality
+pub fn parse_comparison(value: &str, parse_value: fn(&str) -> Option<i64>) -> Option<Comparison> {
+    let operators = [
+        (">=", ">="),
+        ("<=", "<="),
+        ("!=", "!="),
+        ("!", "!="),
+        (">", ">"),
+        ("<", "<"),
+        ("=", "=="),
+    ];
+    let (operator, rest) = operators
+        .iter()
+        .find_map(|(prefix, operator)| value.strip_prefix(prefix).map(|rest| (*operator, rest)))
+        .unwrap_or(("==", value));
+    return Some(Comparison {
+        operator,
+        value: parse_value(rest)?,
+    });
+}
+
+/// Replace a leading `~` by the home directory
+pub fn expand_home(dir: &str) -> String {
+    return match dir.strip_prefix('~') {
+        Some(rest) => format!("{}{}", env::var("HOME").unwrap_or_default(), rest),
+        None => dir.to_string(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_epoch_and_duration() {
+        assert_eq!(parse_time("1640995200"), Some(1640995200));
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let two_days_ago = parse_time("2d").unwrap();
+        assert!((now - 2 * 24 * 3600 - two_days_ago).abs() <= 1);
+        assert_eq!(parse_time("yesterday"), None);
+    }
+
+    #[test]
+    fn comparisons() {
+        let number = |v: &str| v.parse::<i64>().ok();
+        assert_eq!(
+            parse_comparison("!0", number),
+            Some(Comparison {
+                operator: "!=",
+                value: 0
+            })
+        );
+        assert_eq!(parse_comparison("1", number), Some(Comparison::equal(1)));
+        assert_eq!(
+            parse_comparison(">=2", number),
+            Some(Comparison {
+                operator: ">=",
+                value: 2
+            })
+        );
+        assert_eq!(
+            parse_comparison(">1m", parse_seconds),
+            Some(Comparison {
+                operator: ">",
+                value: 60
+            })
+        );
+        assert_eq!(parse_comparison("!x", number), None);
+    }
+
+    #[test]
+    fn only_known_operators() {
+        let comparison: Comparison =
+            serde_json::from_str(r#"{"operator": ">=", "value": 2}"#).unwrap();
+        assert_eq!(comparison.operator, ">=");
+        assert!(
+            serde_json::from_str::<Comparison>(r#"{"operator": "> 0 or 1 ==", "value": 1}"#)
+                .is_err()
+        );
+    }
+}
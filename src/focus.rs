@@ -3,12 +3,19 @@ use std::sync::{Mutex, MutexGuard};
 
 struct AppState {
     focus_session: Option<String>,
+    /// The source the locked session was recorded in, the session ids of the sources overlap
+    focus_session_source: Option<String>,
     focus_dir: Option<String>,
+    focus_source: Option<String>,
+    focus_host: Option<String>,
 }
 static APP_STATE: Lazy<Mutex<AppState>> = Lazy::new(|| {
     Mutex::new(AppState {
         focus_session: None,
+        focus_session_source: None,
         focus_dir: None,
+        focus_source: None,
+        focus_host: None,
     })
 });
 
@@ -16,9 +23,10 @@ fn access_app_state() -> MutexGuard<'static, AppState> {
     APP_STATE.lock().unwrap()
 }
 
-pub fn focus_session(session: &str) {
+pub fn focus_session(session: &str, source: Option<&str>) {
     let mut app_state = access_app_state();
     app_state.focus_session = Some(session.to_string());
+    app_state.focus_session_source = source.map(|s| s.to_string());
 }
 
 pub fn get_focus_session() -> Option<String> {
@@ -29,9 +37,18 @@ pub fn get_focus_session() -> Option<String> {
     };
 }
 
+pub fn get_focus_session_source() -> Option<String> {
+    let app_state = access_app_state();
+    return match app_state.focus_session_source.as_ref() {
+        Some(v) => return Some(v.to_string()),
+        None => None,
+    };
+}
+
 pub fn reset_focus_session() {
     let mut app_state = access_app_state();
     app_state.focus_session = None;
+    app_state.focus_session_source = None;
 }
 
 pub fn focus_dir(dir: &str) {
@@ -51,3 +68,39 @@ pub fn reset_focus_dir() {
     let mut app_state = access_app_state();
     app_state.focus_dir = None;
 }
+
+pub fn focus_source(source: &str) {
+    let mut app_state = access_app_state();
+    app_state.focus_source = Some(source.to_string());
+}
+
+pub fn get_focus_source() -> Option<String> {
+    let app_state = access_app_state();
+    return match app_state.focus_source.as_ref() {
+        Some(v) => return Some(v.to_string()),
+        None => None,
+    };
+}
+
+pub fn reset_focus_source() {
+    let mut app_state = access_app_state();
+    app_state.focus_source = None;
+}
+
+pub fn focus_host(host: &str) {
+    let mut app_state = access_app_state();
+    app_state.focus_host = Some(host.to_string());
+}
+
+pub fn get_focus_host() -> Option<String> {
+    let app_state = access_app_state();
+    return match app_state.focus_host.as_ref() {
+        Some(v) => return Some(v.to_string()),
+        None => None,
+    };
+}
+
+pub fn reset_focus_host() {
+    let mut app_state = access_app_state();
+    app_state.focus_host = None;
+}
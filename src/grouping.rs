@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+/// How entries of the same command are combined into one
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Grouping {
+    None,
+    Command,
+    CommandPlace,
+    CommandHost,
+}
+
+impl Grouping {
+    /// The grouping after this one, as cycled by F5
+    pub fn next(&self) -> Grouping {
+        return match self {
+            Grouping::None => Grouping::Command,
+            Grouping::Command => Grouping::CommandPlace,
+            Grouping::CommandPlace => Grouping::CommandHost,
+            Grouping::CommandHost => Grouping::None,
+        };
+    }
+
+    /// Columns identifying a group, a single entry if not grouped
+    pub fn key_columns(&self) -> &'static str {
+        return match self {
+            Grouping::None => "history.id",
+            Grouping::Command => "history.command_id",
+            Grouping::CommandPlace => "history.command_id, history.place_id",
+            Grouping::CommandHost => "history.command_id, places.host",
+        };
+    }
+
+    pub fn is_grouped(&self) -> bool {
+        return *self != Grouping::None;
+    }
+}
+
+/// Get the grouping for a name, any non matching value is treated as CommandPlace
+pub fn parse_grouping(name: &str) -> Grouping {
+    match name.to_lowercase().as_str() {
+        "none" => Grouping::None,
+        "command" => Grouping::Command,
+        "command+host" => Grouping::CommandHost,
+        _ => Grouping::CommandPlace,
+    }
+}
+
+/// Get the name of a grouping, which is understood by `parse_grouping`
+pub fn grouping_name(grouping: &Grouping) -> &'static str {
+    match grouping {
+        Grouping::None => "none",
+        Grouping::Command => "command",
+        Grouping::CommandPlace => "command+place",
+        Grouping::CommandHost => "command+host",
+    }
+}
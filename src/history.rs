@@ -1,12 +1,28 @@
 extern crate skim;
 use crate::environment::*;
+use crate::redact::redact;
+use crate::theme::{paint, theme};
 use chrono::{DateTime, Local, TimeZone};
 use humantime::format_duration;
+use rusqlite::Row;
+use serde::{Deserialize, Serialize};
 use skim::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use std::time::SystemTime;
 use textwrap::fill;
 
+static MATCH_LOCATION: AtomicBool = AtomicBool::new(false);
+
+/// Match the query against the directory and host too, not only the command
+pub fn toggle_match_location() {
+    MATCH_LOCATION.fetch_xor(true, Ordering::SeqCst);
+}
+
+pub fn is_matching_location() -> bool {
+    return MATCH_LOCATION.load(Ordering::SeqCst);
+}
+
 fn get_epoch_start_of_day() -> u64 {
     let now = SystemTime::now();
     let now_secs = now
@@ -18,6 +34,7 @@ fn get_epoch_start_of_day() -> u64 {
     now_secs - seconds_since_midnight
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct History {
     pub id: i64,
     pub cmd: String,
@@ -28,12 +45,81 @@ pub struct History {
     pub session: i64,
     pub host: String,
     pub dir: String,
-    pub searchrange: [(usize, usize); 1],
+    pub source: String,
+    /// Distinct directories and hosts of all entries of a group
+    pub dirs: Vec<String>,
+    pub hosts: Vec<String>,
+    pub command_id: i64,
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+    /// The command as shown, with the secrets masked unless they are revealed
+    #[serde(skip)]
+    pub shown_cmd: String,
+    /// Directory and host are part of the text, following the command
+    #[serde(skip)]
+    pub match_location: bool,
+    #[serde(skip)]
+    pub searchrange: Vec<(usize, usize)>,
 }
 
 impl History {
     pub const FORMAT_DATE_LENGTH: usize = 10;
     pub const COMMAND_START: usize = (History::FORMAT_DATE_LENGTH + 1);
+    /// Separates the command from the directory, if the location is matched
+    const LOCATION_SEPARATOR: &'static str = "  ";
+    /// Maximal number of directories or hosts listed in the preview
+    const PREVIEW_LIST_LIMIT: usize = 10;
+
+    /// Create an entry from a row of the query built by `build_query_string`
+    pub fn from_row(row: &Row) -> rusqlite::Result<History> {
+        let json_list = |column: &str| -> rusqlite::Result<Vec<String>> {
+            let value: String = row.get(column)?;
+            return Ok(serde_json::from_str(&value).unwrap_or_default());
+        };
+        let mut history = History {
+            id: row.get("id")?,
+            cmd: row.get("cmd")?,
+            start: row.get("start")?,
+            exit_status: row.get("exit_status")?,
+            duration: row.get("duration")?,
+            count: row.get("count")?,
+            session: row.get("session")?,
+            host: row.get("host")?,
+            dir: row.get("dir")?,
+            source: row.get("source")?,
+            dirs: json_list("dirs")?,
+            hosts: json_list("hosts")?,
+            command_id: row.get("command_id")?,
+            tags: Vec::new(),
+            note: row.get("note")?,
+            shown_cmd: String::new(),
+            match_location: false,
+            searchrange: Vec::new(),
+        };
+        if let Some(tags) = row.get::<_, Option<String>>("tags")? {
+            history.tags = tags.split_whitespace().map(|t| t.to_string()).collect();
+            history.tags.sort();
+        }
+        history.update_display();
+        Ok(history)
+    }
+
+    /// Mask the command and set the parts of the text the query is matched against, depending
+    /// on whether the location is matched. This is done once, as skim gets the text of every
+    /// entry on each key stroke. Entries received from the daemon do not have them yet.
+    pub fn update_display(&mut self) {
+        self.shown_cmd = redact(&self.cmd).to_string();
+        self.match_location = is_matching_location();
+        let commandend = self.shown_cmd.len() + History::COMMAND_START;
+        self.searchrange = vec![(History::COMMAND_START, commandend)];
+        if self.match_location {
+            let dirstart = commandend + History::LOCATION_SEPARATOR.len();
+            let hoststart = dirstart + self.dir.len() + 1;
+            self.searchrange.push((dirstart, dirstart + self.dir.len()));
+            self.searchrange
+                .push((hoststart, hoststart + self.host.len()));
+        }
+    }
 
     pub fn command(&self) -> &String {
         return &self.cmd;
@@ -44,6 +130,9 @@ impl History {
     pub fn dir(&self) -> String {
         return self.dir.to_string();
     }
+    pub fn host(&self) -> String {
+        return self.host.to_string();
+    }
 }
 
 impl History {
@@ -62,51 +151,144 @@ impl History {
     }
 
     fn format_or_none(x: Option<i64>) -> String {
-        if x.is_some() {
-            format!("{}", x.unwrap())
-        } else {
-            "\x1b[37;1m<NONE>\x1b[0m".to_string()
+        match x {
+            Some(v) => format!("{}", v),
+            None => paint(theme().missing, "<NONE>"),
         }
     }
 
-    fn format_duration(&self) -> String {
-        if self.duration.is_some() {
-            let duration = Duration::from_secs(self.duration.unwrap() as u64);
-            format_duration(duration).to_string()
-        } else {
-            History::format_or_none(self.duration)
-        }
+    /// The exit status, marked as success or failure
+    fn format_exit_status(&self) -> String {
+        return match self.exit_status {
+            Some(0) => paint(theme().success, "0"),
+            Some(status) => paint(theme().failure, &status.to_string()),
+            None => History::format_or_none(self.exit_status),
+        };
     }
-}
 
-impl SkimItem for History {
-    fn text(&self) -> Cow<str> {
-        let information = format!("{:10} {}", self.format_date(false), self.cmd);
-        Cow::Owned(information)
+    fn format_duration(&self) -> String {
+        match self.duration {
+            Some(d) => format_duration(Duration::from_secs(d as u64)).to_string(),
+            None => History::format_or_none(self.duration),
+        }
     }
 
-    fn preview(&self, _context: PreviewContext) -> ItemPreview {
-        let mut information = String::from(format!("\x1b[1mDetails for {}\x1b[0m\n\n", self.id));
+    /// The details shown in the preview, the command is wrapped at the width
+    pub fn details(&self, width: usize) -> String {
+        let label = theme().label;
+        let mut information = format!("{}\n\n", paint(label, &format!("Details for {}", self.id)));
 
         let mut tformat = |name: &str, value: &str| {
-            information.push_str(&format!("\x1b[1m{:20}\x1b[0m{}\n", name, value));
+            information.push_str(&format!(
+                "{}{}\n",
+                paint(label, &format!("{:20}", name)),
+                value
+            ));
         };
 
         tformat("Runtime", &self.format_duration());
         tformat("Host", &self.host);
         tformat("Executed", &self.count.to_string());
         tformat("Directory", &self.dir);
-        tformat("Exit Status", &History::format_or_none(self.exit_status));
+        tformat("Exit Status", &self.format_exit_status());
         tformat("Session", &self.session.to_string());
         tformat("Start Time", &self.format_date(false));
+        if !self.source.is_empty() {
+            tformat("Source", &self.source);
+        }
+        if !self.tags.is_empty() {
+            tformat("Tags", &self.tags.join(" "));
+        }
+        for (name, values) in [("Directories", &self.dirs), ("Hosts", &self.hosts)] {
+            if values.len() < 2 {
+                continue;
+            }
+            let mut lines: Vec<String> = values
+                .iter()
+                .take(History::PREVIEW_LIST_LIMIT)
+                .cloned()
+                .collect();
+            if values.len() > History::PREVIEW_LIST_LIMIT {
+                lines.push(format!(
+                    "... and {} more",
+                    values.len() - History::PREVIEW_LIST_LIMIT
+                ));
+            }
+            tformat(name, &lines.join(&format!("\n{:20}", "")));
+        }
         information.push_str(&format!(
-            "\x1b[1mCommand\x1b[0m\n\n{}\n",
-            &fill(&self.cmd, _context.width)
+            "{}\n\n{}\n",
+            paint(label, "Command"),
+            &fill(&self.shown_cmd, width)
         ));
-        ItemPreview::AnsiText(information)
+        if let Some(note) = &self.note {
+            information.push_str(&format!(
+                "\n{}\n\n{}\n",
+                paint(label, "Note"),
+                &fill(note, width)
+            ));
+        }
+        return information;
+    }
+}
+
+impl SkimItem for History {
+    fn text(&self) -> Cow<'_, str> {
+        let mut information = format!("{:10} {}", self.format_date(false), self.shown_cmd);
+        if self.match_location {
+            information.push_str(&format!(
+                "{}{} {}",
+                History::LOCATION_SEPARATOR,
+                self.dir,
+                self.host
+            ));
+        }
+        Cow::Owned(information)
+    }
+
+    fn preview(&self, context: PreviewContext) -> ItemPreview {
+        ItemPreview::AnsiText(self.details(context.width))
     }
 
     fn get_matching_ranges(&self) -> Option<&[(usize, usize)]> {
         Some(&self.searchrange)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::strip_escapes;
+    use rusqlite::Connection;
+
+    #[test]
+    fn matching_ranges_include_location() {
+        let conn = Connection::open_in_memory().unwrap();
+        let entry = || {
+            conn.query_row(
+                "select 1 as id, 'terraform apply' as cmd, 0 as start, 0 as exit_status, 1 as duration, 1 as count, 1 as session, 'buildbox' as host, '/srv/infra' as dir, '' as source, json_array('/srv/infra', '/tmp') as dirs, json_array('buildbox') as hosts, 7 as command_id, 'prod infra' as tags, 'needs the VPN' as note",
+                [],
+                History::from_row,
+            )
+            .unwrap()
+        };
+        let only_command = entry();
+        assert_eq!(only_command.searchrange, vec![(11, 26)]);
+        assert_eq!(only_command.dirs, vec!["/srv/infra", "/tmp"]);
+        assert_eq!(only_command.tags, vec!["infra", "prod"]);
+        let details = strip_escapes(&only_command.details(80));
+        assert!(details.contains("Tags                infra prod\n"));
+        assert!(details.ends_with("Note\n\nneeds the VPN\n"));
+
+        toggle_match_location();
+        let with_location = entry();
+        toggle_match_location();
+        let text = with_location.text();
+        let ranges: Vec<&str> = with_location
+            .searchrange
+            .iter()
+            .map(|(start, end)| &text[*start..*end])
+            .collect();
+        assert_eq!(ranges, vec!["terraform apply", "/srv/infra", "buildbox"]);
+    }
+}
@@ -0,0 +1,405 @@
+use crate::arguments::*;
+use crate::database::*;
+use crate::environment::*;
+use rusqlite::{params, Connection, Result};
+use std::fs;
+use std::time::SystemTime;
+
+/// A command read from the history file of another shell
+#[derive(Debug, PartialEq)]
+pub struct ImportedCommand {
+    pub cmd: String,
+    pub start: Option<i64>,
+}
+
+pub struct ImportStats {
+    pub imported: usize,
+    pub duplicates: usize,
+}
+
+/// Parse a bash history file
+///
+/// If `HISTTIMEFORMAT` was set, every entry is preceded by a `#<epoch>` line and may span
+/// multiple lines. Otherwise every line is a command on its own, without a start time, like
+/// the lines written before `HISTTIMEFORMAT` was set.
+pub fn parse_bash_history(content: &str) -> Vec<ImportedCommand> {
+    let is_timestamp = |line: &str| -> bool {
+        line.len() > 1 && line.starts_with('#') && line[1..].chars().all(|c| c.is_ascii_digit())
+    };
+    let timestamped = content.lines().any(is_timestamp);
+
+    let mut commands: Vec<ImportedCommand> = Vec::new();
+    let mut current: Option<ImportedCommand> = None;
+    for line in content.lines() {
+        if !timestamped {
+            if !line.trim().is_empty() {
+                commands.push(ImportedCommand {
+                    cmd: line.to_string(),
+                    start: None,
+                });
+            }
+            continue;
+        }
+        if is_timestamp(line) {
+            if let Some(c) = current.take() {
+                commands.push(c);
+            }
+            current = Some(ImportedCommand {
+                cmd: String::from(""),
+                start: line[1..].parse().ok(),
+            });
+            continue;
+        }
+        match current.as_mut() {
+            Some(c) if c.cmd.is_empty() => c.cmd.push_str(line),
+            Some(c) => {
+                c.cmd.push('\n');
+                c.cmd.push_str(line);
+            }
+            None => commands.push(ImportedCommand {
+                cmd: line.to_string(),
+                start: None,
+            }),
+        }
+    }
+    if let Some(c) = current.take() {
+        commands.push(c);
+    }
+    commands.retain(|c| !c.cmd.trim().is_empty());
+    return commands;
+}
+
+fn unescape_fish(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('\\') => result.push('\\'),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+    return result;
+}
+
+/// Parse a fish history file
+///
+/// The format is YAML like, every entry starts with `- cmd:` followed by an indented `when:`
+/// and an optional `paths:` list. The paths are arguments of the command, not the working
+/// directory, so they are skipped.
+pub fn parse_fish_history(content: &str) -> Vec<ImportedCommand> {
+    let mut commands: Vec<ImportedCommand> = Vec::new();
+    for line in content.lines() {
+        if let Some(cmd) = line.strip_prefix("- cmd: ") {
+            commands.push(ImportedCommand {
+                cmd: unescape_fish(cmd),
+                start: None,
+            });
+        } else if let Some(when) = line.trim_start().strip_prefix("when: ") {
+            if let Some(c) = commands.last_mut() {
+                c.start = when.trim().parse().ok();
+            }
+        }
+    }
+    commands.retain(|c| !c.cmd.trim().is_empty());
+    return commands;
+}
+
+fn is_duplicate(conn: &Connection, cmd: &str, host: &str, start: i64) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "select count() from history
+         join commands on history.command_id = commands.id
+         join places on history.place_id = places.id
+         where commands.argv = ?1 and places.host = ?2 and history.start_time = ?3",
+        params![cmd, host, start],
+        |row| row.get(0),
+    )?;
+    return Ok(count > 0);
+}
+
+/// The commands imported without start time for the place before, oldest first
+fn untimed_imported(conn: &Connection, place_id: i64) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "select argv from untimed_imports
+         join history on history.id = untimed_imports.history_id
+         join commands on history.command_id = commands.id
+         where history.place_id = ?1
+         order by history.start_time, history.id",
+    )?;
+    let rows = stmt.query_map(params![place_id], |row| row.get(0))?;
+    return rows.collect();
+}
+
+/// The number of commands at the beginning of `commands` which were imported at the end of
+/// `imported`, i.e. the file grew or lost its oldest lines since
+fn imported_overlap(imported: &[String], commands: &[&ImportedCommand]) -> usize {
+    let longest = imported.len().min(commands.len());
+    return (0..=longest)
+        .rev()
+        .find(|&n| {
+            imported[imported.len() - n..]
+                .iter()
+                .zip(&commands[..n])
+                .all(|(i, c)| *i == c.cmd)
+        })
+        .unwrap_or(0);
+}
+
+/// Insert the commands into the histdb tables, all of them get a new session id.
+/// Commands which are already in the database for the host with the same start time are
+/// skipped.
+/// Commands without start time which were imported for the host and dir before are skipped by
+/// their order. The others get `fallback_start`, counted back by their position so they keep
+/// their order, the last one gets `fallback_start` itself.
+pub fn import_commands(
+    conn: &mut Connection,
+    commands: &[ImportedCommand],
+    host: &str,
+    dir: &str,
+    fallback_start: i64,
+) -> Result<ImportStats> {
+    let tx = conn.transaction()?;
+    ensure_import_schema(&tx)?;
+    let session = next_session_id(&tx)?;
+    let place_id = insert_place(&tx, host, dir)?;
+    let untimed: Vec<&ImportedCommand> = commands.iter().filter(|c| c.start.is_none()).collect();
+    let mut skipped = imported_overlap(&untimed_imported(&tx, place_id)?, &untimed);
+    let mut stats = ImportStats {
+        imported: 0,
+        duplicates: skipped,
+    };
+    let mut remaining = (untimed.len() - skipped) as i64;
+    for command in commands {
+        let start = match command.start {
+            Some(start) if is_duplicate(&tx, &command.cmd, host, start)? => {
+                stats.duplicates += 1;
+                continue;
+            }
+            Some(start) => start,
+            None if skipped > 0 => {
+                skipped -= 1;
+                continue;
+            }
+            None => {
+                remaining -= 1;
+                fallback_start - remaining
+            }
+        };
+        let command_id = insert_command(&tx, &command.cmd)?;
+        tx.execute(
+            "insert into history (session, command_id, place_id, start_time) values (?1, ?2, ?3, ?4)",
+            params![session, command_id, place_id, start],
+        )?;
+        if command.start.is_none() {
+            tx.execute(
+                "insert into untimed_imports (history_id) values (?1)",
+                params![tx.last_insert_rowid()],
+            )?;
+        }
+        stats.imported += 1;
+    }
+    tx.commit()?;
+    return Ok(stats);
+}
+
+fn default_history_file(shell: &str) -> String {
+    let home = std::env::var("HOME").unwrap_or(String::from(""));
+    return match shell {
+        "fish" => format!("{}/.local/share/fish/fish_history", home),
+        _ => format!("{}/.bash_history", home),
+    };
+}
+
+/// `import bash|fish [FILE] [--host HOST] [--dir DIR]`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let positional = get_positional(args, &["--host", "--dir"]);
+    let shell = positional
+        .first()
+        .ok_or("Usage: import bash|fish [FILE] [--host HOST] [--dir DIR]")?;
+    let file = positional
+        .get(1)
+        .map(|f| f.to_string())
+        .unwrap_or(default_history_file(shell));
+    let content = fs::read_to_string(&file).map_err(|e| format!("Cannot read {}: {}", file, e))?;
+    let commands = match shell.as_str() {
+        "bash" => parse_bash_history(&content),
+        "fish" => parse_fish_history(&content),
+        _ => return Err(format!("Unknown shell {}, use bash or fish", shell)),
+    };
+
+    // Neither bash nor fish record the working directory, so home is used by default
+    let dir = get_option(args, "--dir").unwrap_or(std::env::var("HOME").unwrap_or_default());
+    let host = get_option(args, "--host").unwrap_or(get_current_host());
+    let modified = fs::metadata(&file)
+        .and_then(|m| m.modified())
+        .unwrap_or(SystemTime::now())
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+
+    let mut conn = open_writable(&get_histdb_database()).map_err(|e| e.to_string())?;
+    let stats =
+        import_commands(&mut conn, &commands, &host, &dir, modified).map_err(|e| e.to_string())?;
+    println!(
+        "Imported {} commands from {}, skipped {} duplicates",
+        stats.imported, file, stats.duplicates
+    );
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bash_without_timestamps() {
+        let commands = parse_bash_history("ls -la\n\ncd /tmp\n");
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].cmd, "ls -la");
+        assert_eq!(commands[0].start, None);
+        assert_eq!(commands[1].cmd, "cd /tmp");
+    }
+
+    #[test]
+    fn bash_with_timestamps() {
+        let commands = parse_bash_history(
+            "#1640995200\nls -la\n#1640995210\nfor i in 1 2; do\necho $i\ndone\n",
+        );
+        assert_eq!(
+            commands,
+            vec![
+                ImportedCommand {
+                    cmd: String::from("ls -la"),
+                    start: Some(1640995200)
+                },
+                ImportedCommand {
+                    cmd: String::from("for i in 1 2; do\necho $i\ndone"),
+                    start: Some(1640995210)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bash_lines_before_timestamps() {
+        let commands = parse_bash_history("ls -la\n#1640995200\ncd /tmp\n");
+        assert_eq!(
+            commands,
+            vec![
+                ImportedCommand {
+                    cmd: String::from("ls -la"),
+                    start: None
+                },
+                ImportedCommand {
+                    cmd: String::from("cd /tmp"),
+                    start: Some(1640995200)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fish_history() {
+        let content = "- cmd: ls -la\n  when: 1640995200\n- cmd: cat foo\\\\bar\\nbaz\n  when: 1640995210\n  paths:\n    - foo\n";
+        let commands = parse_fish_history(content);
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].cmd, "ls -la");
+        assert_eq!(commands[0].start, Some(1640995200));
+        assert_eq!(commands[1].cmd, "cat foo\\bar\nbaz");
+        assert_eq!(commands[1].start, Some(1640995210));
+    }
+
+    #[test]
+    fn import_skips_duplicates() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let commands = parse_bash_history("#1640995200\nls -la\n#1640995210\necho hi\n");
+        let stats = import_commands(&mut conn, &commands, "testhost", "/tmp", 0).unwrap();
+        assert_eq!(stats.imported, 2);
+        assert_eq!(stats.duplicates, 0);
+        let stats = import_commands(&mut conn, &commands, "testhost", "/tmp", 0).unwrap();
+        assert_eq!(stats.imported, 0);
+        assert_eq!(stats.duplicates, 2);
+        let stats = import_commands(&mut conn, &commands, "otherhost", "/tmp", 0).unwrap();
+        assert_eq!(stats.imported, 2);
+    }
+
+    #[test]
+    fn import_without_timestamps_keeps_order() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let commands = parse_bash_history(
+            "ls
+git status
+ls
+",
+        );
+        // recorded by zsh before
+        crate::record::record_start(&mut conn, "ls", 1, "testhost", "/tmp", 10).unwrap();
+
+        let stats = import_commands(&mut conn, &commands, "testhost", "/tmp", 100).unwrap();
+        assert_eq!(stats.imported, 3);
+        let stats = import_commands(&mut conn, &commands, "testhost", "/tmp", 100).unwrap();
+        assert_eq!(stats.duplicates, 3);
+        let entries: Vec<(String, i64)> = conn
+            .prepare("select argv, start_time from history join commands on commands.id = command_id where start_time > 10 order by history.id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (String::from("ls"), 98),
+                (String::from("git status"), 99),
+                (String::from("ls"), 100)
+            ]
+        );
+    }
+
+    #[test]
+    fn import_grown_file_without_timestamps() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let commands = parse_bash_history("ls\ngit status\n");
+        let stats = import_commands(&mut conn, &commands, "testhost", "/tmp", 100).unwrap();
+        assert_eq!(stats.imported, 2);
+
+        let commands = parse_bash_history("ls\ngit status\nls\n");
+        let stats = import_commands(&mut conn, &commands, "testhost", "/tmp", 200).unwrap();
+        assert_eq!(stats.imported, 1);
+        assert_eq!(stats.duplicates, 2);
+
+        // bash dropped the oldest line
+        let commands = parse_bash_history("git status\nls\nmake\n");
+        let stats = import_commands(&mut conn, &commands, "testhost", "/tmp", 300).unwrap();
+        assert_eq!(stats.imported, 1);
+        assert_eq!(stats.duplicates, 2);
+
+        let entries: Vec<(String, i64)> = conn
+            .prepare("select argv, start_time from history join commands on commands.id = command_id order by start_time")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (String::from("ls"), 99),
+                (String::from("git status"), 100),
+                (String::from("ls"), 200),
+                (String::from("make"), 300)
+            ]
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use crate::daemon::{DaemonClient, Load};
+use crate::database::HistoryDatabase;
+use crate::environment::*;
+use crate::filter::Filter;
+use crate::grouping::Grouping;
+use crate::history::History;
+use crate::location::Location;
+use crate::query::{build_federated_query_string, QueryContext};
+use rusqlite::{Connection, InterruptHandle};
+use skim::prelude::*;
+use std::collections::HashSet;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Number of most recent history entries which are loaded first, so the list shows up quickly
+/// even if grouping all the history takes a while
+const FIRST_PAGE_SIZE: usize = 500;
+
+/// Where the entries are loaded from
+pub enum Backend {
+    Database(HistoryDatabase),
+    Daemon(DaemonClient),
+}
+
+impl Backend {
+    /// Load the entries through the daemon if it is running, otherwise open the databases
+    pub fn open() -> Result<Backend, String> {
+        let databases = get_histdb_databases();
+        if let Some(client) = DaemonClient::connect(&get_daemon_socket(), &databases) {
+            return Ok(Backend::Daemon(client));
+        }
+        let database =
+            HistoryDatabase::open(&databases).map_err(|e| format!("Cannot open database {}", e))?;
+        return Ok(Backend::Database(database));
+    }
+
+    /// Get a warning for every database lacking one of the recommended indexes, the daemon
+    /// warns about them itself when it starts
+    pub fn check_indexes(&self) -> Vec<String> {
+        return match self {
+            Backend::Database(database) => database.check_indexes(),
+            Backend::Daemon(_) => Vec::new(),
+        };
+    }
+}
+
+/// Allows to stop loading entries, e.g. if the user switches to another location
+#[derive(Clone, Default)]
+pub struct LoadControl {
+    cancelled: Arc<AtomicBool>,
+    interrupt: Arc<Mutex<Option<Arc<InterruptHandle>>>>,
+    connection: Arc<Mutex<Option<UnixStream>>>,
+}
+
+impl LoadControl {
+    /// Stop loading, a running query is interrupted
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.interrupt.lock().unwrap().as_ref() {
+            handle.interrupt();
+        }
+        if let Some(connection) = self.connection.lock().unwrap().as_ref() {
+            let _ = connection.shutdown(Shutdown::Both);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.cancelled.load(Ordering::SeqCst);
+    }
+
+    fn set_interrupt_handle(&self, handle: Arc<InterruptHandle>) {
+        let mut interrupt = self.interrupt.lock().unwrap();
+        if self.is_cancelled() {
+            handle.interrupt();
+        }
+        *interrupt = Some(handle);
+    }
+
+    /// The connection to the daemon the entries are currently read from
+    pub fn set_connection(&self, stream: UnixStream) {
+        let mut connection = self.connection.lock().unwrap();
+        if self.is_cancelled() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        *connection = Some(stream);
+    }
+}
+
+type EntryKey = (String, String, String, String, i64);
+
+/// The groups of the most recent entries must not be sent again with the rest
+fn entry_key(entry: &History, grouping: Grouping) -> EntryKey {
+    let source = entry.source.to_string();
+    let cmd = entry.cmd.to_string();
+    return match grouping {
+        Grouping::None => (
+            source,
+            String::new(),
+            String::new(),
+            String::new(),
+            entry.id,
+        ),
+        Grouping::Command => (source, cmd, String::new(), String::new(), 0),
+        Grouping::CommandPlace => (
+            source,
+            cmd,
+            entry.host.to_string(),
+            entry.dir.to_string(),
+            0,
+        ),
+        Grouping::CommandHost => (source, cmd, entry.host.to_string(), String::new(), 0),
+    };
+}
+
+fn send_entries(
+    conn: &Connection,
+    query: &str,
+    mut send: impl FnMut(History) -> bool,
+) -> Result<(), String> {
+    let mut stmt = conn
+        .prepare_cached(query)
+        .map_err(|e| format!("Cannot get result from database {}", e))?;
+    let entries = stmt
+        .query_map([], History::from_row)
+        .map_err(|e| format!("Cannot get result from database {}", e))?;
+    for entry in entries.flatten() {
+        if !send(entry) {
+            break;
+        }
+    }
+    return Ok(());
+}
+
+/// Load the entries of the location and send them to skim, most recent first.
+///
+/// Only entries matching the `filter` are loaded. If grouped, the groups among the most recent
+/// entries are sent first, counting all their executions. The remaining groups follow as soon as
+/// SQLite has grouped the whole history.
+pub fn read_entries(
+    backend: &Backend,
+    location: &Location,
+    grouping: Grouping,
+    filter: Filter,
+    tx_item: SkimItemSender,
+    control: LoadControl,
+) {
+    let context = QueryContext::current();
+    // checked on every load, as tags and notes may have been added meanwhile
+    let local = match backend {
+        Backend::Database(database) => match database.annotated_sources() {
+            Ok(sources) => {
+                control.set_interrupt_handle(database.interrupt_handle());
+                Some((database.conn.lock().unwrap(), sources))
+            }
+            Err(e) => {
+                let _ = tx_item.send(Arc::new(format!("Cannot get result from database {}", e)));
+                return;
+            }
+        },
+        Backend::Daemon(_) => None,
+    };
+
+    let mut seen = HashSet::new();
+    let mut filters = Vec::new();
+    if grouping.is_grouped() {
+        filters.push(Filter {
+            recent: Some(FIRST_PAGE_SIZE),
+            ..filter.clone()
+        });
+    }
+    filters.push(filter);
+    for filter in filters {
+        if control.is_cancelled() {
+            break;
+        }
+        // entries sent before are skipped
+        let send = |entry: History| -> bool {
+            if control.is_cancelled() {
+                return false;
+            }
+            if seen.insert(entry_key(&entry, grouping)) {
+                let _ = tx_item.send(Arc::new(entry));
+            }
+            return true;
+        };
+        let result = match (backend, &local) {
+            (Backend::Daemon(client), _) => {
+                let load = Load {
+                    location: *location,
+                    grouping,
+                    filter,
+                    context: context.clone(),
+                };
+                client.load(&load, &control, send)
+            }
+            (_, Some((conn, sources))) => {
+                let query =
+                    build_federated_query_string(location, grouping, &filter, sources, &context);
+                send_entries(conn, &query, send)
+            }
+            (_, None) => Ok(()),
+        };
+        if let Err(e) = result {
+            if !control.is_cancelled() {
+                let _ = tx_item.send(Arc::new(e));
+            }
+            break;
+        }
+    }
+    drop(tx_item);
+}
@@ -1,9 +1,38 @@
 use enum_map::Enum;
+use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Enum, Copy, Clone)]
+#[derive(PartialEq, Enum, Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum Location {
     Session,
     Directory,
     Machine,
     Everywhere,
 }
+
+/// Get the location for a name, any non matching value is treated as Session
+pub fn parse_location(name: &str) -> Location {
+    return find_location(name).unwrap_or(Location::Session);
+}
+
+/// Get the location for a name, None if it does not name one
+pub fn find_location(name: &str) -> Option<Location> {
+    match name.to_lowercase().as_str() {
+        "session" => Some(Location::Session),
+        "directory" => Some(Location::Directory),
+        "machine" => Some(Location::Machine),
+        "host" => Some(Location::Machine),
+        "everywhere" => Some(Location::Everywhere),
+        "*" => Some(Location::Everywhere),
+        _ => None,
+    }
+}
+
+/// Get the name of a location, which is understood by `parse_location`
+pub fn location_name(location: &Location) -> &'static str {
+    match location {
+        Location::Session => "session",
+        Location::Directory => "directory",
+        Location::Machine => "machine",
+        Location::Everywhere => "everywhere",
+    }
+}
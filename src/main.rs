@@ -1,105 +1,179 @@
+#![allow(clippy::needless_return)]
 extern crate skim;
+mod annotation;
+mod arguments;
+mod clipboard;
+mod config;
+mod daemon;
+mod database;
 mod environment;
+mod export;
+mod filter;
 mod focus;
+mod grouping;
 mod history;
+mod import;
+mod loader;
 mod location;
+mod merge;
+mod popup;
+mod preset;
+mod qualifier;
 mod query;
+mod record;
+mod redact;
+mod screen;
+mod secrets;
+mod state;
+mod theme;
 mod title;
 
+use crate::annotation::edit_annotation;
+use crate::clipboard::copy_to_clipboard;
+use crate::config::get_config_warning;
+use crate::database::get_sources;
 use crate::environment::*;
+use crate::filter::Filter;
 use crate::focus::focus_dir;
+use crate::focus::focus_host;
 use crate::focus::focus_session;
+use crate::focus::focus_source;
 use crate::focus::get_focus_dir;
+use crate::focus::get_focus_host;
 use crate::focus::get_focus_session;
+use crate::focus::get_focus_source;
 use crate::focus::reset_focus_dir;
+use crate::focus::reset_focus_host;
 use crate::focus::reset_focus_session;
-use crate::history::History;
+use crate::focus::reset_focus_source;
+use crate::grouping::Grouping;
+use crate::history::{toggle_match_location, History};
+use crate::loader::{read_entries, Backend, LoadControl};
 use crate::location::Location;
-use crate::query::build_query_string;
-use crate::title::generate_title;
+use crate::popup::{parse_popup_size, show_in_popup, write_popup_result};
+use crate::preset::{get_preset, next_preset, Preset};
+use crate::qualifier::{
+    apply_qualifiers, format_qualifiers, merge_qualifiers, split_query, Qualifier,
+};
+use crate::redact::get_redact_warnings;
+use crate::redact::toggle_reveal;
+use crate::screen::{get_terminal_size, parse_height, AlternateScreen};
+use crate::state::{load_state, save_state};
+use crate::theme::strip_escapes;
+use crate::title::{generate_title, HeaderLine};
 
-use rusqlite::{Connection, OpenFlags, Result};
+use rusqlite::Result;
 use skim::prelude::*;
 use std::env;
 use std::thread;
 
-fn read_entries(location: &Location, grouped: bool, tx_item: SkimItemSender) {
-    let conn_res =
-        Connection::open_with_flags(get_histdb_database(), OpenFlags::SQLITE_OPEN_READ_ONLY);
-    if conn_res.is_err() {
-        let _ = tx_item.send(Arc::new("Cannot open database"));
-        drop(tx_item);
-        return;
-    }
-    let conn = conn_res.unwrap();
-    let s = build_query_string(&location, grouped);
-
-    let stmt_result = conn.prepare(&s);
-    if stmt_result.is_err() {
-        let _ = tx_item.send(Arc::new(format!(
-            "Cannot get result from database {}",
-            stmt_result.err().unwrap()
-        )));
-        drop(tx_item);
-        return;
-    }
-    let mut stmt = stmt_result.unwrap();
-
-    let cats = stmt.query_map([], |row| {
-        let cmd: String = row.get("cmd")?;
-        let commandend = cmd.len() as usize;
-        Ok(History {
-            id: row.get("id")?,
-            cmd: cmd,
-            start: row.get("start")?,
-            exit_status: row.get("exit_status")?,
-            duration: row.get("duration")?,
-            count: row.get("count")?,
-            session: row.get("session")?,
-            host: row.get("host")?,
-            dir: row.get("dir")?,
-            searchrange: [(
-                History::COMMAND_START,
-                commandend + (History::COMMAND_START),
-            )],
-        })
-    });
-    for person in cats.unwrap() {
-        if person.is_ok() {
-            let x = person.unwrap();
-            let _ = tx_item.send(Arc::new(x));
-        }
-    }
-    drop(tx_item);
-}
-
 struct SelectionResult {
     selected_cmd: Option<String>,
     abort: bool,
+    /// Shown in the header of the next run, e.g. whether copying succeeded
+    notice: Option<String>,
 }
 
 fn get_starting_location() -> Location {
     let mut location = get_default_tab();
-    if location == Location::Session && get_current_session_id() == "" {
+    if location == Location::Session && get_current_session_id().is_empty() {
         location = Location::Directory;
     }
     location
 }
 
-fn show_history(thequery: String) -> Result<String, String> {
-    let mut location = get_starting_location();
-    let mut grouped = true;
+/// Switch to the location and the query of the preset, if it defines them
+fn apply_preset(preset: &Preset, location: &mut Location, query: &mut String) {
+    if let Some(preset_location) = preset.location {
+        *location = preset_location;
+    }
+    if let Some(preset_query) = &preset.query {
+        *query = preset_query.to_string();
+    }
+}
+
+/// Move the qualifiers of the query to the active ones, if all of them are valid.
+/// Returns whether the query contained qualifiers.
+fn take_qualifiers(
+    query: &mut String,
+    qualifiers: &mut Vec<Qualifier>,
+    location: &mut Location,
+) -> Result<bool, String> {
+    let (found, pattern) = split_query(query);
+    if found.is_empty() {
+        return Ok(false);
+    }
+    let mut merged = qualifiers.clone();
+    merge_qualifiers(&mut merged, found);
+    apply_qualifiers(&Filter::default(), &merged)?;
+    // other hosts are only shown everywhere
+    if merged.iter().any(|q| q.key == "host") {
+        *location = Location::Everywhere;
+    }
+    *qualifiers = merged;
+    *query = pattern;
+    return Ok(true);
+}
+
+fn show_history(thequery: String, preset_name: Option<String>) -> Result<String, String> {
+    let mut preset = match preset_name {
+        Some(name) => Some(get_preset(&name)?),
+        None => None,
+    };
+    let backend = Arc::new(Backend::open()?);
+    let state = load_state();
+    let mut location = state.location.unwrap_or(get_starting_location());
+    let mut grouping = state.grouping.unwrap_or(Grouping::CommandPlace);
     let mut query = thequery;
+    if let Some(preset) = &preset {
+        // a query given on the command line wins over the one of the preset
+        let given_query = query.clone();
+        apply_preset(preset, &mut location, &mut query);
+        if !given_query.is_empty() {
+            query = given_query;
+        }
+    }
+    let mut qualifiers: Vec<Qualifier> = Vec::new();
+    let mut qualifier_error = take_qualifiers(&mut query, &mut qualifiers, &mut location).err();
+    // shown in the header, output before skim would be hidden by it
+    let mut warnings = backend.check_indexes();
+    warnings.extend(get_config_warning().cloned());
+    warnings.extend(get_redact_warnings());
+    let mut notice: Option<String> = match warnings.is_empty() {
+        true => None,
+        false => Some(warnings.join("; ")),
+    };
+    let height = get_height_option();
+    let layout = get_layout_option();
+    let prompt = get_prompt_option();
+    let margin = get_margin_option();
+    let terminal_height = get_terminal_size().map(|(_, h)| h).unwrap_or(24);
+    // skim only clears its lines between the runs, instead of leaving the alternate screen.
+    // Inline the terminal content above the picker stays visible instead.
+    let mut screen = match parse_height(&height, terminal_height) >= terminal_height {
+        true => AlternateScreen::enter(),
+        false => None,
+    };
     loop {
-        let title = generate_title(&location);
+        let (terminal_width, terminal_height) = get_terminal_size().unwrap_or((80, 24));
+        let title = generate_title(
+            &location,
+            grouping,
+            preset.as_ref().map(|p| p.name.as_str()),
+            &format_qualifiers(&qualifiers),
+            qualifier_error.as_deref().or(notice.take().as_deref()),
+            terminal_width,
+            parse_height(&height, terminal_height),
+        );
         let window_option = get_preview_window_option();
         let color_options = get_color();
 
         let options = SkimOptionsBuilder::default()
-            .height(Some("100%"))
+            .height(Some(&height))
+            .margin(Some(&margin))
             .multi(false)
-            .reverse(true)
-            .prompt(Some("history >>"))
+            .layout(&layout)
+            .prompt(Some(&prompt))
             .query(Some(&query))
             .color(Some(&color_options))
             .bind(vec![
@@ -110,60 +184,180 @@ fn show_history(thequery: String) -> Result<String, String> {
                 "f5:abort",
                 "f6:abort",
                 "f7:abort",
+                "f8:abort",
+                "f9:abort",
                 "ctrl-r:abort",
+                "alt-r:abort",
+                "alt-p:abort",
+                "alt-q:abort",
+                "alt-m:abort",
+                "alt-c:abort",
+                "alt-y:abort",
+                "alt-e:abort",
+                "alt-t:abort",
                 "ctrl-u:half-page-up",
                 "ctrl-d:half-page-down",
             ])
-            .header(Some(&title))
+            .header_lines(title.lines().count())
             .preview(Some("")) // preview should be specified to enable preview window
             .preview_window(Some(&window_option)) // preview should be specified to enable preview window
             .nosort(get_nosort_option())
+            .no_clear_start(screen.is_some())
             .build()
             .unwrap();
 
         let (tx_item, rx_item): (SkimItemSender, SkimItemReceiver) = unbounded();
+        for line in title.lines() {
+            let _ = tx_item.send(Arc::new(HeaderLine::new(line)));
+        }
 
+        let control = LoadControl::default();
+        let reader_control = control.clone();
+        let reader_backend = backend.clone();
+        let preset_filter = preset
+            .as_ref()
+            .map(|p| p.filter.clone())
+            .unwrap_or_default();
+        let filter = apply_qualifiers(&preset_filter, &qualifiers).unwrap_or(preset_filter);
         let handle = thread::spawn(move || {
-            read_entries(&location, grouped, tx_item);
+            read_entries(
+                &reader_backend,
+                &location,
+                grouping,
+                filter,
+                tx_item,
+                reader_control,
+            );
         });
 
         let selected_items = Skim::run_with(&options, Some(rx_item));
+        // do not wait for entries nobody will look at
+        control.cancel();
         handle.join().unwrap();
 
-        let selection_result = process_result(&selected_items, &mut location, &mut grouped);
+        if let Some(output) = selected_items
+            .as_ref()
+            .filter(|o| o.final_key == Key::Enter)
+        {
+            // Enter applies the qualifiers typed into the query, instead of selecting
+            let mut new_query = output.query.to_string();
+            match take_qualifiers(&mut new_query, &mut qualifiers, &mut location) {
+                Ok(false) => {}
+                Ok(true) => {
+                    qualifier_error = None;
+                    query = new_query;
+                    continue;
+                }
+                Err(e) => {
+                    qualifier_error = Some(e);
+                    query = new_query;
+                    continue;
+                }
+            }
+        }
+
+        let selection_result = process_result(&selected_items, &mut location, &mut grouping);
+        notice = selection_result.notice;
+        save_state(&location, grouping);
         if selection_result.abort {
             return Err("Aborted".to_string());
         }
-        if selection_result.selected_cmd.is_some() {
-            return Ok(selection_result.selected_cmd.unwrap());
+        if let Some(cmd) = selection_result.selected_cmd {
+            return Ok(cmd);
+        }
+        let output = selected_items.unwrap();
+        query = output.query;
+        if output.final_key == Key::Alt('p') {
+            let name = next_preset(preset.as_ref().map(|p| p.name.as_str()));
+            preset = name.and_then(|n| get_preset(&n).ok());
+            if let Some(preset) = &preset {
+                apply_preset(preset, &mut location, &mut query);
+                qualifier_error = take_qualifiers(&mut query, &mut qualifiers, &mut location).err();
+            }
+        }
+        if output.final_key == Key::Alt('q') {
+            qualifiers.clear();
+            qualifier_error = None;
+        }
+        let history = output
+            .selected_items
+            .first()
+            .and_then(|item| (**item).as_any().downcast_ref::<History>());
+        if let (Key::Alt('e'), Some(history)) = (output.final_key, history) {
+            // the editor leaves the alternate screen when it exits
+            let full_screen = screen.take().is_some();
+            notice = Some(match edit_annotation(history) {
+                Ok(()) => String::from("Saved the tags and the note"),
+                Err(e) => e,
+            });
+            if full_screen {
+                screen = AlternateScreen::enter();
+            }
+        }
+        if let (Key::Alt('t'), Some(history)) = (output.final_key, history) {
+            cycle_tag_qualifier(&mut qualifiers, &history.tags);
         }
-        query = selected_items.unwrap().query;
+    }
+}
+
+/// Filter by the next tag of the entry, after the last one the tag filter is removed
+fn cycle_tag_qualifier(qualifiers: &mut Vec<Qualifier>, tags: &[String]) {
+    let next = match qualifiers.iter().find(|q| q.key == "tag") {
+        None => tags.first(),
+        Some(current) => tags.iter().skip_while(|t| **t != current.value).nth(1),
+    };
+    let next = next.map(|tag| Qualifier {
+        key: String::from("tag"),
+        value: tag.to_string(),
+    });
+    qualifiers.retain(|q| q.key != "tag");
+    qualifiers.extend(next);
+}
+
+/// Focus the next database, after the last one all databases are shown again
+fn cycle_focus_source() {
+    let names: Vec<String> = get_sources(&get_histdb_databases())
+        .into_iter()
+        .map(|s| s.name)
+        .collect();
+    if names.len() < 2 {
+        return;
+    }
+    let next = match get_focus_source() {
+        None => names.first(),
+        Some(current) => names.iter().skip_while(|n| **n != current).nth(1),
+    };
+    match next {
+        Some(name) => focus_source(name),
+        None => reset_focus_source(),
     }
 }
 
 fn process_result(
     selected_items: &Option<SkimOutput>,
     loc: &mut Location,
-    grouped: &mut bool,
+    grouping: &mut Grouping,
 ) -> SelectionResult {
     if selected_items.is_some() {
         let sel = selected_items.as_ref().unwrap();
+        // there is none if no entry matches the query
+        let history = sel
+            .selected_items
+            .first()
+            .and_then(|item| (**item).as_any().downcast_ref::<History>());
         match sel.final_key {
             Key::ESC | Key::Ctrl('c') | Key::Ctrl('d') | Key::Ctrl('z') => {
                 return SelectionResult {
                     selected_cmd: None,
                     abort: true,
+                    notice: None,
                 };
             }
             Key::Enter => {
                 return SelectionResult {
-                    selected_cmd: Some(format!(
-                        "{}",
-                        ((*sel.selected_items[0]).as_any().downcast_ref::<History>())
-                            .unwrap()
-                            .command()
-                    )),
+                    selected_cmd: history.map(|h| h.command().to_string()),
                     abort: false,
+                    notice: None,
                 };
             }
             Key::F(1) => {
@@ -179,30 +373,62 @@ fn process_result(
                 *loc = Location::Everywhere;
             }
             Key::F(5) => {
-                *grouped = !*grouped;
+                *grouping = grouping.next();
             }
             Key::F(6) => {
                 if get_focus_session().is_none() {
-                    focus_session(
-                        &((*sel.selected_items[0]).as_any().downcast_ref::<History>())
-                            .unwrap()
-                            .session(),
-                    );
+                    if let Some(history) = history {
+                        focus_session(&history.session(), Some(&history.source));
+                    }
                 } else {
                     reset_focus_session();
                 }
             }
             Key::F(7) => {
                 if get_focus_dir().is_none() {
-                    focus_dir(
-                        &((*sel.selected_items[0]).as_any().downcast_ref::<History>())
-                            .unwrap()
-                            .dir(),
-                    );
+                    if let Some(history) = history {
+                        focus_dir(&history.dir());
+                    }
                 } else {
                     reset_focus_dir();
                 }
             }
+            Key::F(8) => {
+                cycle_focus_source();
+            }
+            Key::F(9) => {
+                if get_focus_host().is_none() {
+                    if let Some(history) = history {
+                        focus_host(&history.host());
+                    }
+                } else {
+                    reset_focus_host();
+                }
+            }
+            Key::Alt('r') => {
+                toggle_reveal();
+            }
+            Key::Alt('m') => {
+                toggle_match_location();
+            }
+            Key::Alt('c') | Key::Alt('y') => {
+                let notice = match (history, sel.final_key) {
+                    (None, _) => None,
+                    (Some(history), Key::Alt('c')) => Some(
+                        copy_to_clipboard(history.command())
+                            .map(|_| String::from("Copied the command")),
+                    ),
+                    (Some(history), _) => Some(
+                        copy_to_clipboard(&strip_escapes(&history.details(usize::MAX)))
+                            .map(|_| String::from("Copied the details")),
+                    ),
+                };
+                return SelectionResult {
+                    selected_cmd: None,
+                    abort: false,
+                    notice: notice.map(|n| n.unwrap_or_else(|e| e)),
+                };
+            }
             Key::Ctrl('r') => {
                 *loc = match *loc {
                     Location::Session => Location::Directory,
@@ -216,33 +442,66 @@ fn process_result(
         return SelectionResult {
             selected_cmd: None,
             abort: false,
+            notice: None,
         };
     } else {
         return SelectionResult {
             selected_cmd: None,
             abort: true,
+            notice: None,
         };
     }
 }
 
-fn main() -> Result<()> {
-    let _conn =
-        Connection::open_with_flags(get_histdb_database(), OpenFlags::SQLITE_OPEN_READ_ONLY);
+fn run_subcommand(result: Result<(), String>) -> ! {
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
 
-    let args: Vec<String> = env::args().collect();
-    let query = |args: Vec<String>| -> String {
-        if args.len() > 1 {
-            return args[1].to_string();
+fn main() -> Result<()> {
+    let mut args: Vec<String> = env::args().collect();
+    let mut preset = None;
+    let mut popup_output = None;
+    while let Some(option @ ("--preset" | "--popup-output")) = args.get(1).map(|a| a.as_str()) {
+        if args.len() < 3 {
+            eprintln!("Usage: zsh-histdb-skim [--preset NAME] [-- QUERY]");
+            std::process::exit(1);
         }
-        return "".to_string();
-    }(args);
-
-    if query == "--version" {
-        println!("v0.9.6");
-        std::process::exit(1);
+        match option {
+            "--preset" => preset = Some(args[2].to_string()),
+            _ => popup_output = Some(args[2].to_string()),
+        }
+        args.drain(1..3);
     }
+    let query = match args.get(1).map(|a| a.as_str()) {
+        Some("--version") => {
+            println!("v0.9.6");
+            std::process::exit(1);
+        }
+        Some("import") => run_subcommand(import::run(&args[2..])),
+        Some("export") => run_subcommand(export::run(&args[2..])),
+        Some("merge") => run_subcommand(merge::run(&args[2..])),
+        Some("scan-secrets") => run_subcommand(secrets::run(&args[2..])),
+        Some("record") => run_subcommand(record::run(&args[2..])),
+        Some("daemon") => run_subcommand(daemon::run(&args[2..])),
+        Some("--") => args[2..].join(" "),
+        Some(q) => q.to_string(),
+        None => "".to_string(),
+    };
 
-    let result = show_history(query);
+    let popup_size = parse_popup_size(&get_tmux_popup_option());
+    let result = match popup_size {
+        Some(size) if popup_output.is_none() && is_in_tmux() => {
+            show_in_popup(&query, preset.as_deref(), size)
+        }
+        _ => show_history(query, preset),
+    };
+    if let Some(path) = popup_output {
+        run_subcommand(write_popup_result(&path, &result));
+    }
     if result.is_ok() {
         println!("{}", result.ok().unwrap());
     } else {
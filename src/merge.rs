@@ -0,0 +1,138 @@
+use crate::arguments::*;
+use crate::database::*;
+use crate::environment::*;
+use rusqlite::{params, Connection, Result};
+use std::fs;
+
+pub struct MergeStats {
+    pub merged: usize,
+    pub duplicates: usize,
+}
+
+/// Merge the history of another histdb database into this one.
+///
+/// Commands and places are deduplicated by their unique keys (`argv` resp. `host` and `dir`),
+/// the history rows get the ids of this database. Sessions are shifted behind the sessions
+/// already known, so they do not collide. Rows which already exist (same command, place and
+/// start time) are skipped, so merging the same database twice does not duplicate anything.
+pub fn merge_database(conn: &mut Connection, other: &str) -> Result<MergeStats> {
+    conn.execute("attach database ?1 as other", params![other])?;
+    let result = merge_attached(conn);
+    conn.execute("detach database other", [])?;
+    return result;
+}
+
+fn merge_attached(conn: &mut Connection) -> Result<MergeStats> {
+    let tx = conn.transaction()?;
+    tx.execute(
+        "insert into main.commands (argv) select argv from other.commands",
+        [],
+    )?;
+    tx.execute(
+        "insert into main.places (host, dir) select host, dir from other.places",
+        [],
+    )?;
+    let session_offset: i64 = tx.query_row(
+        "select coalesce(max(session), 0) from main.history",
+        [],
+        |row| row.get(0),
+    )?;
+    let total: usize = tx.query_row("select count() from other.history", [], |row| {
+        row.get::<_, i64>(0)
+    })? as usize;
+    let merged = tx.execute(
+        "insert into main.history (session, command_id, place_id, exit_status, start_time, duration)
+         select o.session + ?1, c.id, p.id, o.exit_status, o.start_time, o.duration
+         from other.history o
+         join other.commands oc on o.command_id = oc.id
+         join main.commands c on c.argv = oc.argv
+         join other.places op on o.place_id = op.id
+         join main.places p on p.host = op.host and p.dir = op.dir
+         where not exists (
+             select 1 from main.history h
+             where h.command_id = c.id and h.place_id = p.id and h.start_time is o.start_time
+         )
+         order by o.id",
+        params![session_offset],
+    )?;
+    tx.commit()?;
+    return Ok(MergeStats {
+        merged,
+        duplicates: total - merged,
+    });
+}
+
+/// `merge FILE`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let positional = get_positional(args, &[]);
+    let other = positional.first().ok_or("Usage: merge FILE")?;
+    let database = get_histdb_database();
+    let same_file = match (fs::canonicalize(other), fs::canonicalize(&database)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    };
+    if same_file {
+        return Err(format!("{} is the current database", other));
+    }
+    if fs::metadata(other).is_err() {
+        return Err(format!("Cannot find database {}", other));
+    }
+
+    let mut conn = open_writable(&database).map_err(|e| e.to_string())?;
+    let stats = merge_database(&mut conn, other).map_err(|e| e.to_string())?;
+    println!(
+        "Merged {} history entries from {}, skipped {} duplicates",
+        stats.merged, other, stats.duplicates
+    );
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_remaps_ids_and_sessions() {
+        let path = std::env::temp_dir().join(format!("histdb-merge-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let other = open_writable(path.to_str().unwrap()).unwrap();
+        add_history(&other, 1, "make", "laptop", "/src", 100);
+        add_history(&other, 1, "echo shared", "laptop", "/src", 101);
+        add_history(&other, 2, "echo shared", "workstation", "/home", 50);
+        drop(other);
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        add_history(&conn, 1, "echo shared", "workstation", "/home", 50);
+        add_history(&conn, 3, "ls", "workstation", "/home", 60);
+
+        let stats = merge_database(&mut conn, path.to_str().unwrap()).unwrap();
+        assert_eq!(stats.merged, 2);
+        assert_eq!(stats.duplicates, 1);
+
+        let commands: i64 = conn
+            .query_row("select count() from commands", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(commands, 3);
+        let sessions: Vec<i64> = conn
+            .prepare("select distinct session from history order by session")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(sessions, vec![1, 3, 4]);
+        let merged_cmd: String = conn
+            .query_row(
+                "select argv from history join commands on history.command_id = commands.id where start_time = 100",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(merged_cmd, "make");
+
+        let stats = merge_database(&mut conn, path.to_str().unwrap()).unwrap();
+        assert_eq!(stats.merged, 0);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::environment::*;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::Command;
+
+/// Size of the popup, if it is only enabled
+const DEFAULT_SIZE: (&str, &str) = ("80%", "60%");
+
+/// Variables passed to the popup, tmux starts it with the environment of the tmux server
+const PASSED_VARIABLES: &[&str] = &["HISTDB_", "XDG_", "HOSTNAME", "NO_COLOR"];
+
+/// Get width and height of the popup from HISTDB_SKIM_TMUX_POPUP, None if it is disabled
+pub fn parse_popup_size(value: &str) -> Option<(String, String)> {
+    return match value.to_lowercase().as_str() {
+        "" | "false" | "0" => None,
+        "true" | "1" => Some((DEFAULT_SIZE.0.to_string(), DEFAULT_SIZE.1.to_string())),
+        _ => match value.split_once(',') {
+            Some((width, height)) => Some((width.to_string(), height.to_string())),
+            None => Some((value.to_string(), value.to_string())),
+        },
+    };
+}
+
+fn shell_quote(value: &str) -> String {
+    return format!("'{}'", value.replace('\'', "'\\''"));
+}
+
+/// Run the picker in a tmux popup, so the content of the terminal stays visible.
+/// The picker in the popup writes its result into a temporary file, which is read afterwards.
+pub fn show_in_popup(
+    query: &str,
+    preset: Option<&str>,
+    size: (String, String),
+) -> Result<String, String> {
+    let output = env::temp_dir().join(format!("zsh-histdb-skim-{}.out", std::process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&output)
+        .map_err(|e| format!("Cannot create {} {}", output.display(), e))?;
+    let exe = env::current_exe().map_err(|e| format!("Cannot find executable {}", e))?;
+    let mut args = vec![
+        exe.to_string_lossy().to_string(),
+        String::from("--popup-output"),
+        output.to_string_lossy().to_string(),
+    ];
+    if let Some(preset) = preset {
+        args.push(String::from("--preset"));
+        args.push(preset.to_string());
+    }
+    args.push(String::from("--"));
+    args.push(query.to_string());
+    let command: Vec<String> = args.iter().map(|a| shell_quote(a)).collect();
+
+    let mut tmux = Command::new("tmux");
+    tmux.args(["display-popup", "-E", "-w", &size.0, "-h", &size.1])
+        .args(["-d", &get_current_dir()]);
+    for (key, value) in env::vars() {
+        if PASSED_VARIABLES.iter().any(|v| key.starts_with(v)) {
+            tmux.arg("-e").arg(format!("{}={}", key, value));
+        }
+    }
+    let status = tmux.arg(command.join(" ")).status();
+    let result = fs::read_to_string(&output).unwrap_or_default();
+    let _ = fs::remove_file(&output);
+    status.map_err(|e| format!("Cannot run tmux {}", e))?;
+    return match result.split_once('\n') {
+        Some(("ok", cmd)) => Ok(cmd.to_string()),
+        Some(("error", e)) => Err(e.to_string()),
+        _ => Err("Aborted".to_string()),
+    };
+}
+
+/// Pass the result of the picker in the popup to `show_in_popup`
+pub fn write_popup_result(path: &str, result: &Result<String, String>) -> Result<(), String> {
+    let content = match result {
+        Ok(cmd) => format!("ok\n{}", cmd),
+        Err(e) => format!("error\n{}", e),
+    };
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|e| format!("Cannot write {} {}", path, e))?;
+    return file
+        .write_all(content.as_bytes())
+        .map_err(|e| format!("Cannot write {} {}", path, e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn popup_size() {
+        assert_eq!(parse_popup_size(""), None);
+        assert_eq!(parse_popup_size("false"), None);
+        assert_eq!(
+            parse_popup_size("true"),
+            Some((String::from("80%"), String::from("60%")))
+        );
+        assert_eq!(
+            parse_popup_size("100%,20"),
+            Some((String::from("100%"), String::from("20")))
+        );
+        assert_eq!(
+            parse_popup_size("90%"),
+            Some((String::from("90%"), String::from("90%")))
+        );
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+}
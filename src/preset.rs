@@ -0,0 +1,110 @@
+use crate::config::{get_config, PresetConfig};
+use crate::filter::{expand_home, parse_time, Comparison, Filter};
+use crate::location::{find_location, Location};
+
+/// A named search from the config file
+pub struct Preset {
+    pub name: String,
+    pub location: Option<Location>,
+    pub filter: Filter,
+    pub query: Option<String>,
+}
+
+pub fn build_preset(name: &str, config: &PresetConfig) -> Result<Preset, String> {
+    let time = |value: &Option<String>| -> Result<Option<i64>, String> {
+        return match value {
+            Some(v) => match parse_time(v) {
+                Some(t) => Ok(Some(t)),
+                None => Err(format!("Invalid time {} in preset {}", v, name)),
+            },
+            None => Ok(None),
+        };
+    };
+    let location = match config.location.as_deref() {
+        Some(value) => Some(find_location(value).ok_or(format!(
+            "Invalid location {} in preset {}, use session, directory, machine or everywhere",
+            value, name
+        ))?),
+        None => None,
+    };
+    return Ok(Preset {
+        name: name.to_string(),
+        // other hosts are only shown everywhere, like with the host: qualifier
+        location: match config.host {
+            Some(_) => Some(Location::Everywhere),
+            None => location,
+        },
+        filter: Filter {
+            since: time(&config.since)?,
+            until: time(&config.until)?,
+            host: config.host.clone(),
+            dir: config.dir.as_deref().map(expand_home),
+            exit_status: config.exit_status.map(Comparison::equal),
+            ..Default::default()
+        },
+        query: config.query.clone(),
+    });
+}
+
+pub fn get_preset(name: &str) -> Result<Preset, String> {
+    return match get_config().presets.get(name) {
+        Some(config) => build_preset(name, config),
+        None => Err(format!("Unknown preset {}", name)),
+    };
+}
+
+pub fn has_presets() -> bool {
+    return !get_config().presets.is_empty();
+}
+
+/// Get the preset after the current one, after the last one no preset is used
+pub fn next_preset(current: Option<&str>) -> Option<String> {
+    let mut names = get_config().presets.keys();
+    return match current {
+        None => names.next().cloned(),
+        Some(current) => names.skip_while(|n| *n != current).nth(1).cloned(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_filter() {
+        let config = PresetConfig {
+            location: Some(String::from("everywhere")),
+            dir: Some(String::from("/srv/infra/")),
+            exit_status: Some(0),
+            since: Some(String::from("30d")),
+            query: Some(String::from("kubectl")),
+            ..Default::default()
+        };
+        let preset = build_preset("deploys", &config).unwrap();
+        assert!(preset.location == Some(Location::Everywhere));
+        assert_eq!(preset.filter.dir.as_deref(), Some("/srv/infra/"));
+        assert_eq!(preset.filter.exit_status, Some(Comparison::equal(0)));
+        assert!(preset.filter.since.is_some());
+        assert_eq!(preset.query.as_deref(), Some("kubectl"));
+
+        let config = PresetConfig {
+            since: Some(String::from("last week")),
+            ..Default::default()
+        };
+        assert!(build_preset("broken", &config).is_err());
+
+        let config = PresetConfig {
+            location: Some(String::from("machine")),
+            host: Some(String::from("buildbox-3")),
+            ..Default::default()
+        };
+        let preset = build_preset("buildbox", &config).unwrap();
+        assert!(preset.location == Some(Location::Everywhere));
+
+        let config = PresetConfig {
+            location: Some(String::from("everwhere")),
+            ..Default::default()
+        };
+        assert!(build_preset("typo", &config).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use crate::filter::{expand_home, parse_comparison, parse_seconds, parse_time, Filter};
+
+/// Keys which are parsed out of the query, e.g. `dir:src exit:!0 host:ci since:2d dur:>60s tag:deploy`
+pub const QUALIFIERS: &[&str] = &["dir", "exit", "host", "since", "until", "dur", "tag"];
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Qualifier {
+    pub key: String,
+    pub value: String,
+}
+
+/// Split the query into the qualifiers and the remaining fuzzy pattern for skim
+pub fn split_query(query: &str) -> (Vec<Qualifier>, String) {
+    let mut qualifiers = Vec::new();
+    let mut pattern = Vec::new();
+    for word in query.split_whitespace() {
+        match word.split_once(':') {
+            Some((key, value)) if QUALIFIERS.contains(&key) && !value.is_empty() => {
+                qualifiers.push(Qualifier {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                });
+            }
+            _ => pattern.push(word),
+        }
+    }
+    if qualifiers.is_empty() {
+        return (qualifiers, query.to_string());
+    }
+    return (qualifiers, pattern.join(" "));
+}
+
+/// Add the qualifiers, replacing existing ones with the same key
+pub fn merge_qualifiers(current: &mut Vec<Qualifier>, qualifiers: Vec<Qualifier>) {
+    for qualifier in qualifiers {
+        current.retain(|q| q.key != qualifier.key);
+        current.push(qualifier);
+    }
+}
+
+pub fn format_qualifiers(qualifiers: &[Qualifier]) -> String {
+    return qualifiers
+        .iter()
+        .map(|q| format!("{}:{}", q.key, q.value))
+        .collect::<Vec<String>>()
+        .join(" ");
+}
+
+/// Restrict the filter by the qualifiers. `dir:` starting with `/` or `~` matches the
+/// directory and below, otherwise every directory containing the value.
+pub fn apply_qualifiers(filter: &Filter, qualifiers: &[Qualifier]) -> Result<Filter, String> {
+    let mut filter = filter.clone();
+    for qualifier in qualifiers {
+        let value = qualifier.value.as_str();
+        let invalid = || format!("Invalid qualifier {}:{}", qualifier.key, value);
+        match qualifier.key.as_str() {
+            "dir" if value.starts_with('/') || value.starts_with('~') => {
+                filter.dir = Some(expand_home(value))
+            }
+            "dir" => filter.dir_contains = Some(value.to_string()),
+            "host" => filter.host = Some(value.to_string()),
+            "tag" => filter.tag = Some(value.to_string()),
+            "exit" => {
+                filter.exit_status =
+                    Some(parse_comparison(value, |v| v.parse().ok()).ok_or_else(invalid)?)
+            }
+            "dur" => {
+                filter.duration = Some(parse_comparison(value, parse_seconds).ok_or_else(invalid)?)
+            }
+            "since" => filter.since = Some(parse_time(value).ok_or_else(invalid)?),
+            "until" => filter.until = Some(parse_time(value).ok_or_else(invalid)?),
+            _ => return Err(invalid()),
+        }
+    }
+    return Ok(filter);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Comparison;
+
+    #[test]
+    fn splits_qualifiers_from_pattern() {
+        let (qualifiers, pattern) = split_query("git dir:src exit:!0 push origin:main");
+        assert_eq!(pattern, "git push origin:main");
+        assert_eq!(format_qualifiers(&qualifiers), "dir:src exit:!0");
+        let (qualifiers, pattern) = split_query("  ls  -la ");
+        assert!(qualifiers.is_empty());
+        assert_eq!(pattern, "  ls  -la ");
+    }
+
+    #[test]
+    fn builds_filter() {
+        let (qualifiers, _) = split_query("dir:terraform host:ci dur:>60s exit:!0 since:2d");
+        let filter = apply_qualifiers(&Filter::default(), &qualifiers).unwrap();
+        assert_eq!(filter.dir_contains.as_deref(), Some("terraform"));
+        assert_eq!(filter.host.as_deref(), Some("ci"));
+        assert_eq!(
+            filter.duration,
+            Some(Comparison {
+                operator: ">",
+                value: 60
+            })
+        );
+        assert_eq!(
+            filter.exit_status,
+            Some(Comparison {
+                operator: "!=",
+                value: 0
+            })
+        );
+        assert!(filter.since.is_some());
+
+        let (qualifiers, _) = split_query("dir:/srv/infra exit:maybe");
+        assert_eq!(
+            apply_qualifiers(&Filter::default(), &qualifiers).err(),
+            Some(String::from("Invalid qualifier exit:maybe"))
+        );
+    }
+
+    #[test]
+    fn replaces_qualifiers() {
+        let mut current = split_query("dir:src exit:0").0;
+        merge_qualifiers(&mut current, split_query("exit:1 host:ci").0);
+        assert_eq!(format_qualifiers(&current), "dir:src exit:1 host:ci");
+    }
+}
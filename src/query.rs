@@ -1,85 +1,288 @@
+use crate::database::Source;
 use crate::environment::*;
+use crate::filter::Filter;
 use crate::focus::get_focus_dir;
+use crate::focus::get_focus_host;
 use crate::focus::get_focus_session;
+use crate::focus::get_focus_session_source;
+use crate::focus::get_focus_source;
+use crate::grouping::Grouping;
 use crate::location::Location;
+use serde::{Deserialize, Serialize};
 
-pub fn build_query_string(theloc: &Location, grouped: bool) -> String {
+/// Escape a value to be used within single quotes in a query
+fn quote(value: &str) -> String {
+    return value.replace('\'', "''");
+}
+
+/// The shell the picker was started from and the locks, which the locations refer to
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct QueryContext {
+    pub session: Option<i64>,
+    pub dir: String,
+    pub host: String,
+    pub focus_session: Option<i64>,
+    /// The source of the locked session, the others are not queried while it is locked
+    pub focus_session_source: Option<String>,
+    pub focus_dir: Option<String>,
+    pub focus_host: Option<String>,
+    pub focus_source: Option<String>,
+}
+
+impl QueryContext {
+    /// The context of this process, from the environment and the locks of the picker
+    pub fn current() -> QueryContext {
+        return QueryContext {
+            session: get_current_session_id().parse().ok(),
+            dir: get_current_dir(),
+            host: get_current_host(),
+            focus_session: get_focus_session().and_then(|s| s.parse().ok()),
+            focus_session_source: get_focus_session_source(),
+            focus_dir: get_focus_dir(),
+            focus_host: get_focus_host(),
+            focus_source: get_focus_source(),
+        };
+    }
+}
+
+#[cfg(test)]
+pub fn build_query_string(theloc: &Location, grouping: Grouping) -> String {
+    return build_filtered_query_string(theloc, grouping, &Filter::default());
+}
+
+pub fn build_filtered_query_string(
+    theloc: &Location,
+    grouping: Grouping,
+    filter: &Filter,
+) -> String {
+    let mut query = build_select(
+        theloc,
+        grouping,
+        filter,
+        &QueryContext::current(),
+        None,
+        "",
+        false,
+    );
+    query.push_str(" order by start desc");
+    return query;
+}
+
+/// Build a query over all sources, the results are combined with `union all`.
+/// If a source is focused, only this one is queried. The same applies to the source of a
+/// locked session, the session ids of the other sources belong to other sessions.
+pub fn build_federated_query_string(
+    theloc: &Location,
+    grouping: Grouping,
+    filter: &Filter,
+    sources: &[Source],
+    context: &QueryContext,
+) -> String {
+    let is_focused = |focus: &Option<String>, source: &Source| -> bool {
+        return focus.is_none() || focus.as_ref() == Some(&source.name);
+    };
+    let mut selects: Vec<String> = sources
+        .iter()
+        .filter(|s| is_focused(&context.focus_source, s))
+        .filter(|s| context.focus_session.is_none() || is_focused(&context.focus_session_source, s))
+        .map(|s| {
+            build_select(
+                theloc,
+                grouping,
+                filter,
+                context,
+                Some(&s.schema),
+                &s.name,
+                s.annotated,
+            )
+        })
+        .collect();
+    if selects.is_empty() {
+        selects.push(build_select(
+            theloc, grouping, filter, context, None, "", false,
+        ));
+    }
+    let mut query = selects.join(" union all ");
+    query.push_str(" order by start desc");
+    return query;
+}
+
+/// Build the query of a single source. The tags and notes are only selected if the source
+/// is `annotated`, as the tables do not exist otherwise.
+fn build_select(
+    theloc: &Location,
+    grouping: Grouping,
+    filter: &Filter,
+    context: &QueryContext,
+    schema: Option<&str>,
+    source: &str,
+    annotated: bool,
+) -> String {
+    let session = |session: Option<i64>| -> String {
+        return session
+            .map(|s| s.to_string())
+            .unwrap_or(String::from("null"));
+    };
+    let table = |name: &str| -> String {
+        return match schema {
+            Some(s) => format!("{}.{} as {}", s, name, name),
+            None => name.to_string(),
+        };
+    };
     let mut query = String::from("select history.id as id, commands.argv as cmd,");
-    if !grouped {
+    if !grouping.is_grouped() {
         query.push_str(" start_time")
     } else {
         query.push_str(" max(start_time)")
     }
     query.push_str(" as start, exit_status, duration,");
-    if !grouped {
-        query.push_str(" 1")
+    if !grouping.is_grouped() {
+        query.push_str(
+            " 1 as count, json_array(places.dir) as dirs, json_array(places.host) as hosts,",
+        )
     } else {
-        query.push_str(" count()")
+        query.push_str(" count() as count, json_group_array(distinct places.dir) as dirs, json_group_array(distinct places.host) as hosts,")
     }
-    query.push_str(" as count, history.session as session, places.host as host, places.dir as dir");
-    query.push_str(" from history");
-    query.push_str(" left join commands on history.command_id = commands.id");
-    query.push_str(" left join places on history.place_id = places.id");
-    match theloc {
-        Location::Session | Location::Directory | Location::Machine => {
-            query.push_str(" where");
-        }
-        _ => {}
-    };
+    query.push_str(" history.session as session, places.host as host, places.dir as dir,");
+    query.push_str(&format!(" '{}' as source,", quote(source)));
+    query.push_str(" history.command_id as command_id,");
+    if annotated {
+        query.push_str(&format!(
+            " (select group_concat(tag, ' ') from {} where command_tags.command_id = history.command_id) as tags,",
+            table("command_tags")
+        ));
+        query.push_str(&format!(
+            " (select note from {} where command_notes.command_id = history.command_id) as note",
+            table("command_notes")
+        ));
+    } else {
+        query.push_str(" null as tags, null as note");
+    }
+    query.push_str(&format!(" from {}", table("history")));
+    query.push_str(&format!(
+        " left join {} on history.command_id = commands.id",
+        table("commands")
+    ));
+    query.push_str(&format!(
+        " left join {} on history.place_id = places.id",
+        table("places")
+    ));
+
+    let mut conditions: Vec<String> = Vec::new();
     match theloc {
         Location::Session => {
-            let session = get_focus_session().unwrap_or(get_current_session_id());
-            query.push_str(&format!(" session == {} and", session));
+            let focus_session = context.focus_session.or(context.session);
+            conditions.push(format!("session == {}", session(focus_session)));
         }
 
         Location::Directory => {
-            let session = get_focus_dir().unwrap_or(get_current_dir());
-            query.push_str(&format!(" places.dir like '{}' and", session));
+            let dir = context.focus_dir.as_ref().unwrap_or(&context.dir);
+            conditions.push(format!("places.dir like '{}'", quote(dir)));
         }
 
-        _ => {}
-    };
-    match theloc {
         Location::Machine | Location::Everywhere => {
-            let session = get_focus_session();
-            let dir = get_focus_dir();
-            if dir.is_some() {
-                query.push_str(&format!(" places.dir like '{}' and", dir.unwrap()));
+            if let Some(dir) = &context.focus_dir {
+                conditions.push(format!("places.dir like '{}'", quote(dir)));
             }
-            if session.is_some() {
-                query.push_str(&format!(" session == {} and", session.unwrap()));
+            if context.focus_session.is_some() {
+                conditions.push(format!("session == {}", session(context.focus_session)));
             }
         }
-
-        _ => {}
     };
+    if let Some(since) = filter.since {
+        conditions.push(format!("start_time >= {}", since));
+    }
+    if let Some(until) = filter.until {
+        conditions.push(format!("start_time <= {}", until));
+    }
     match theloc {
         Location::Session | Location::Directory | Location::Machine => {
-            query.push_str(&format!(" places.host == '{}'", &get_current_host()));
+            let host = context.focus_host.as_ref().unwrap_or(&context.host);
+            conditions.push(format!("places.host == '{}'", quote(host)));
+        }
+        Location::Everywhere => {
+            if let Some(host) = &context.focus_host {
+                conditions.push(format!("places.host == '{}'", quote(host)));
+            }
+            if let Some(host) = &filter.host {
+                conditions.push(format!("places.host == '{}'", quote(host)));
+            }
         }
-        _ => {}
     };
-    if grouped {
-        query.push_str(" group by history.command_id, history.place_id");
+    if let Some(recent) = filter.recent {
+        let mut recent_query = format!("select {}", grouping.key_columns());
+        recent_query.push_str(&format!(" from {}", table("history")));
+        recent_query.push_str(&format!(
+            " left join {} on history.place_id = places.id",
+            table("places")
+        ));
+        if !conditions.is_empty() {
+            recent_query.push_str(" where ");
+            recent_query.push_str(&conditions.join(" and "));
+        }
+        recent_query.push_str(&format!(" order by start_time desc limit {}", recent));
+        conditions.push(format!(
+            "({}) in ({})",
+            grouping.key_columns(),
+            recent_query
+        ));
+    }
+    if let Some(dir) = &filter.dir {
+        let dir = dir.trim_end_matches('/');
+        conditions.push(format!(
+            "(places.dir == '{}' or substr(places.dir, 1, {}) == '{}/')",
+            quote(dir),
+            dir.chars().count() + 1,
+            quote(dir)
+        ));
+    }
+    if let Some(dir) = &filter.dir_contains {
+        conditions.push(format!("instr(places.dir, '{}') > 0", quote(dir)));
+    }
+    if let Some(exit_status) = filter.exit_status {
+        conditions.push(format!(
+            "exit_status {} {}",
+            exit_status.operator, exit_status.value
+        ));
+    }
+    if let Some(duration) = filter.duration {
+        conditions.push(format!("duration {} {}", duration.operator, duration.value));
+    }
+    match (&filter.tag, annotated) {
+        (Some(tag), true) => conditions.push(format!(
+            "history.command_id in (select command_id from {} where tag == '{}')",
+            table("command_tags"),
+            quote(tag)
+        )),
+        // nothing is tagged yet
+        (Some(_), false) => conditions.push(String::from("0")),
+        (None, _) => {}
+    }
+    if !conditions.is_empty() {
+        query.push_str(" where ");
+        query.push_str(&conditions.join(" and "));
+    }
+    if grouping.is_grouped() {
+        query.push_str(&format!(" group by {}", grouping.key_columns()));
     }
-    query.push_str(" order by start desc");
     return query;
 }
 
 #[cfg(test)]
-mod query {
+mod tests {
     use super::*;
+    use crate::filter::Comparison;
     use regex::Regex;
 
     #[test]
     fn has_select_fields() {
-        for l in vec![
+        for l in [
             Location::Session,
             Location::Directory,
             Location::Machine,
             Location::Everywhere,
         ] {
-            let query = build_query_string(&l, true);
+            let query = build_query_string(&l, Grouping::CommandPlace);
             assert!(query.contains("history.id as id"));
             assert!(query.contains("exit_status"));
             assert!(query.contains("start"));
@@ -93,24 +296,24 @@ mod query {
     #[test]
     fn contains_host() {
         let re_host = Regex::new(r"host == '.*'").unwrap();
-        for l in vec![Location::Session, Location::Directory, Location::Machine] {
-            let query = build_query_string(&l, true);
+        for l in [Location::Session, Location::Directory, Location::Machine] {
+            let query = build_query_string(&l, Grouping::CommandPlace);
             assert!(re_host.is_match(&query));
         }
-        let query = build_query_string(&Location::Everywhere, true);
+        let query = build_query_string(&Location::Everywhere, Grouping::CommandPlace);
         assert!(!re_host.is_match(&query));
     }
 
     #[test]
     fn contains_grouping() {
         let re_group = Regex::new(r"group by history.command_id, history.place_id").unwrap();
-        for l in vec![
+        for l in [
             Location::Session,
             Location::Directory,
             Location::Machine,
             Location::Everywhere,
         ] {
-            let query = build_query_string(&l, true);
+            let query = build_query_string(&l, Grouping::CommandPlace);
             assert!(re_group.is_match(&query));
         }
     }
@@ -119,13 +322,13 @@ mod query {
     fn contains_no_grouping_if_disabled() {
         let re_group = Regex::new(r"group by history.command_id, history.place_id").unwrap();
         let re_only_group = Regex::new(r"group").unwrap();
-        for l in vec![
+        for l in [
             Location::Session,
             Location::Directory,
             Location::Machine,
             Location::Everywhere,
         ] {
-            let query = build_query_string(&l, false);
+            let query = build_query_string(&l, Grouping::None);
             assert!(!re_only_group.is_match(&query));
             assert!(!re_group.is_match(&query));
         }
@@ -133,8 +336,8 @@ mod query {
 
     #[test]
     fn for_session() {
-        let query = build_query_string(&Location::Session, true);
-        let re_session = Regex::new(r"session == (\d*) and").unwrap();
+        let query = build_query_string(&Location::Session, Grouping::CommandPlace);
+        let re_session = Regex::new(r"session == (\d+|null) and").unwrap();
         let re_host = Regex::new(r"host == '.*'").unwrap();
         let re_group = Regex::new(r"group by history.command_id, history.place_id").unwrap();
         assert!(re_session.is_match(&query));
@@ -144,7 +347,7 @@ mod query {
 
     #[test]
     fn for_directory() {
-        let query = build_query_string(&Location::Directory, false);
+        let query = build_query_string(&Location::Directory, Grouping::None);
         let re_directory = Regex::new(r"places.dir like '.*' and").unwrap();
         let re_group = Regex::new(r"group by history.command_id, history.place_id").unwrap();
         assert!(re_directory.is_match(&query));
@@ -153,8 +356,8 @@ mod query {
 
     #[test]
     fn for_machine() {
-        let query = build_query_string(&Location::Machine, true);
-        let re_session = Regex::new(r"session == (\d*) and").unwrap();
+        let query = build_query_string(&Location::Machine, Grouping::CommandPlace);
+        let re_session = Regex::new(r"session == (\d+|null) and").unwrap();
         let re_place = Regex::new(r"dir like '.*' and").unwrap();
         let re_host = Regex::new(r"host == '.*'").unwrap();
         let re_group = Regex::new(r"group by history.command_id, history.place_id").unwrap();
@@ -165,8 +368,8 @@ mod query {
     }
     #[test]
     fn for_everywhere() {
-        let query = build_query_string(&Location::Everywhere, true);
-        let re_session = Regex::new(r"session == (\d*) and").unwrap();
+        let query = build_query_string(&Location::Everywhere, Grouping::CommandPlace);
+        let re_session = Regex::new(r"session == (\d+|null) and").unwrap();
         let re_place = Regex::new(r"dir like '.*' and").unwrap();
         let re_host = Regex::new(r"host == '.*'").unwrap();
         let re_group = Regex::new(r"group by history.command_id, history.place_id").unwrap();
@@ -175,4 +378,161 @@ mod query {
         assert!(!re_host.is_match(&query));
         assert!(re_group.is_match(&query));
     }
+
+    #[test]
+    fn with_filter() {
+        let filter = Filter {
+            since: Some(1640995200),
+            until: Some(1640995300),
+            host: Some(String::from("other'host")),
+            ..Default::default()
+        };
+        let query = build_filtered_query_string(&Location::Everywhere, Grouping::None, &filter);
+        assert!(query.contains("where start_time >= 1640995200 and start_time <= 1640995300"));
+        assert!(query.contains("places.host == 'other''host'"));
+        let query = build_filtered_query_string(&Location::Machine, Grouping::None, &filter);
+        assert!(!query.contains("other''host"));
+    }
+
+    #[test]
+    fn federated() {
+        let sources = vec![
+            Source {
+                schema: String::from("main"),
+                name: String::from("zsh-history"),
+                annotated: false,
+            },
+            Source {
+                schema: String::from("source1"),
+                name: String::from("container"),
+                annotated: true,
+            },
+        ];
+        let query = build_federated_query_string(
+            &Location::Everywhere,
+            Grouping::CommandPlace,
+            &Filter::default(),
+            &sources,
+            &QueryContext::default(),
+        );
+        assert!(query.contains("'zsh-history' as source,"));
+        assert!(query.contains("null as note from main.history as history"));
+        assert!(query.contains(" union all "));
+        assert!(query.contains("'container' as source,"));
+        assert!(query.contains("as note from source1.history as history"));
+        assert!(query.contains("left join source1.places as places"));
+        assert!(query.contains("from source1.command_notes as command_notes"));
+        assert!(!query.contains("main.command_tags"));
+        assert!(
+            query.ends_with("group by history.command_id, history.place_id order by start desc")
+        );
+    }
+
+    #[test]
+    fn session_lock_of_a_source() {
+        let sources = vec![
+            Source {
+                schema: String::from("main"),
+                name: String::from("zsh-history"),
+                annotated: false,
+            },
+            Source {
+                schema: String::from("source1"),
+                name: String::from("container"),
+                annotated: false,
+            },
+        ];
+        let context = QueryContext {
+            focus_session: Some(42),
+            focus_session_source: Some(String::from("container")),
+            ..Default::default()
+        };
+        let query = build_federated_query_string(
+            &Location::Everywhere,
+            Grouping::CommandPlace,
+            &Filter::default(),
+            &sources,
+            &context,
+        );
+        assert!(query.contains("from source1.history as history"));
+        assert!(query.contains("session == 42"));
+        assert!(!query.contains("main.history"));
+        assert!(!query.contains(" union all "));
+    }
+
+    #[test]
+    fn recent_entries_first() {
+        let filter = Filter {
+            recent: Some(500),
+            ..Default::default()
+        };
+        let query =
+            build_filtered_query_string(&Location::Directory, Grouping::CommandPlace, &filter);
+        let re_recent = Regex::new(r"places.dir like '.*' and places.host == '.*' and \(history.command_id, history.place_id\) in \(select history.command_id, history.place_id from history left join places on history.place_id = places.id where places.dir like '.*' and places.host == '.*' order by start_time desc limit 500\) group by").unwrap();
+        assert!(re_recent.is_match(&query));
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::database::ensure_schema(&conn).unwrap();
+        assert!(conn.prepare(&query).is_ok());
+    }
+
+    #[test]
+    fn under_dir_with_exit_status() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::database::ensure_schema(&conn).unwrap();
+        for (dir, exit_status) in [
+            ("/srv/infra", 0),
+            ("/srv/infra/k8s", 0),
+            ("/srv/infra2", 0),
+            ("/srv/infra/k8s", 1),
+        ] {
+            let id = crate::database::add_history(&conn, 1, "kubectl apply", "host", dir, 1);
+            crate::record::record_finish(&conn, id, exit_status, 1).unwrap();
+        }
+        let filter = Filter {
+            dir: Some(String::from("/srv/infra/")),
+            exit_status: Some(Comparison::equal(0)),
+            ..Default::default()
+        };
+        let query = build_filtered_query_string(&Location::Everywhere, Grouping::None, &filter);
+        let mut dirs: Vec<String> = conn
+            .prepare(&query)
+            .unwrap()
+            .query_map([], |row| row.get("dir"))
+            .unwrap()
+            .flatten()
+            .collect();
+        dirs.sort();
+        assert_eq!(dirs, vec!["/srv/infra", "/srv/infra/k8s"]);
+    }
+
+    #[test]
+    fn grouping_modes() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::database::ensure_schema(&conn).unwrap();
+        let filter = Filter {
+            recent: Some(500),
+            ..Default::default()
+        };
+        for (grouping, group_by) in [
+            (Grouping::Command, Some("group by history.command_id")),
+            (
+                Grouping::CommandHost,
+                Some("group by history.command_id, places.host"),
+            ),
+            (Grouping::None, None),
+        ] {
+            let query = build_filtered_query_string(&Location::Everywhere, grouping, &filter);
+            match group_by {
+                Some(group_by) => assert!(query.contains(group_by)),
+                None => assert!(!query.contains("group by")),
+            }
+            assert!(query.contains(&format!(
+                "({}) in (select {} from",
+                grouping.key_columns(),
+                grouping.key_columns()
+            )));
+            assert!(conn.prepare(&query).is_ok());
+        }
+    }
 }
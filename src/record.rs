@@ -0,0 +1,121 @@
+use crate::database::*;
+use crate::environment::*;
+use rusqlite::{params, Connection, Result};
+use std::time::SystemTime;
+
+fn now() -> i64 {
+    return SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+}
+
+/// Insert the history entry of a command which was just started, returns its id
+pub fn record_start(
+    conn: &mut Connection,
+    cmd: &str,
+    session: i64,
+    host: &str,
+    dir: &str,
+    start_time: i64,
+) -> Result<i64> {
+    let tx = conn.transaction()?;
+    let command_id = insert_command(&tx, cmd)?;
+    let place_id = insert_place(&tx, host, dir)?;
+    tx.execute(
+        "insert into history (session, command_id, place_id, start_time) values (?1, ?2, ?3, ?4)",
+        params![session, command_id, place_id, start_time],
+    )?;
+    let id = tx.last_insert_rowid();
+    tx.commit()?;
+    return Ok(id);
+}
+
+/// Set the exit status and the duration of a history entry
+pub fn record_finish(conn: &Connection, id: i64, exit_status: i64, end_time: i64) -> Result<usize> {
+    return conn.execute(
+        "update history set exit_status = ?2, duration = ?3 - start_time where id = ?1",
+        params![id, exit_status, end_time],
+    );
+}
+
+const USAGE: &str = "Usage: record start COMMAND | record finish ID EXIT_STATUS | record session";
+
+/// `record start COMMAND` prints the id of the new entry, `record finish ID EXIT_STATUS`
+/// completes it and `record session` prints a new session id.
+/// The session and host are taken from HISTDB_SESSION and HISTDB_HOST, the directory is the
+/// current one.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let number = |value: Option<&String>| -> Result<i64, String> {
+        return value
+            .and_then(|v| v.parse::<i64>().ok())
+            .ok_or(USAGE.to_string());
+    };
+    let mut conn =
+        open_recorder(&get_histdb_database(), get_wal_option()).map_err(|e| e.to_string())?;
+    match args.first().map(|a| a.as_str()) {
+        Some("start") => {
+            let cmd = match args.get(1).map(|a| a.as_str()) {
+                Some("--") => args[2..].join(" "),
+                _ => args[1..].join(" "),
+            };
+            if cmd.is_empty() {
+                return Err(USAGE.to_string());
+            }
+            let session = get_current_session_id()
+                .parse::<i64>()
+                .map_err(|_| "HISTDB_SESSION is not set")?;
+            let id = record_start(
+                &mut conn,
+                &cmd,
+                session,
+                &get_current_host(),
+                &get_current_dir(),
+                now(),
+            )
+            .map_err(|e| e.to_string())?;
+            println!("{}", id);
+        }
+        Some("finish") => {
+            let id = number(args.get(1))?;
+            let exit_status = number(args.get(2))?;
+            record_finish(&conn, id, exit_status, now()).map_err(|e| e.to_string())?;
+        }
+        Some("session") => {
+            println!("{}", next_session_id(&conn).map_err(|e| e.to_string())?);
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_and_finish() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        let first = record_start(&mut conn, "make", 3, "host", "/src", 100).unwrap();
+        let second = record_start(&mut conn, "make", 3, "host", "/src", 200).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(record_finish(&conn, first, 2, 142).unwrap(), 1);
+
+        let entries: Vec<(String, Option<i64>, Option<i64>)> = conn
+            .prepare("select argv, exit_status, duration from history left join commands on command_id = commands.id order by history.id")
+            .unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (String::from("make"), Some(2), Some(42)),
+                (String::from("make"), None, None)
+            ]
+        );
+        assert_eq!(next_session_id(&conn).unwrap(), 4);
+    }
+}
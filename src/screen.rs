@@ -0,0 +1,57 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use tuikit::output::Output;
+use tuikit::raw::get_tty;
+
+/// Get width and height of the terminal
+pub fn get_terminal_size() -> Option<(usize, usize)> {
+    let output = Output::new(Box::new(get_tty().ok()?)).ok()?;
+    return output.terminal_size().ok();
+}
+
+/// Get the number of lines of the picker, the height is given in lines or percent of the
+/// terminal like the skim option
+pub fn parse_height(height: &str, terminal_height: usize) -> usize {
+    let lines = match height.strip_suffix('%') {
+        Some(percent) => percent.parse::<usize>().unwrap_or(100).min(100) * terminal_height / 100,
+        None => height.parse::<usize>().unwrap_or(terminal_height),
+    };
+    return lines.min(terminal_height);
+}
+
+/// Keeps the terminal on the alternate screen while skim is restarted, e.g. when switching
+/// the location, so the shell does not show up in between
+pub struct AlternateScreen {
+    tty: File,
+}
+
+impl AlternateScreen {
+    /// Switch to the alternate screen, returns None if there is no terminal
+    pub fn enter() -> Option<AlternateScreen> {
+        let mut tty = OpenOptions::new().write(true).open("/dev/tty").ok()?;
+        tty.write_all(b"\x1b[?1049h").ok()?;
+        tty.flush().ok()?;
+        return Some(AlternateScreen { tty });
+    }
+}
+
+impl Drop for AlternateScreen {
+    fn drop(&mut self) {
+        let _ = self.tty.write_all(b"\x1b[?1049l");
+        let _ = self.tty.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn height() {
+        assert_eq!(parse_height("100%", 40), 40);
+        assert_eq!(parse_height("40%", 40), 16);
+        assert_eq!(parse_height("15", 40), 15);
+        assert_eq!(parse_height("60", 40), 40);
+        assert_eq!(parse_height("invalid", 40), 40);
+    }
+}
@@ -0,0 +1,383 @@
+use crate::arguments::*;
+use crate::config::{get_config, get_config_warning};
+use crate::database::*;
+use crate::environment::*;
+use crate::redact::{compile_patterns, find_secrets, mask_ranges, BUILTIN_PATTERNS};
+use regex::Regex;
+use rusqlite::{params, Connection, OpenFlags, Result};
+
+/// Minimal length of a token to be checked for its entropy
+const ENTROPY_MIN_LENGTH: usize = 20;
+/// Shannon entropy (bits per character) from which a token is considered random
+const ENTROPY_THRESHOLD: f64 = 3.7;
+
+/// A command, or the note of a command, containing secrets
+pub struct SecretFinding {
+    pub command_id: i64,
+    /// The command, or the note if `note` is set
+    pub argv: String,
+    pub note: bool,
+    pub history_ids: Vec<i64>,
+    pub detectors: Vec<&'static str>,
+    pub ranges: Vec<(usize, usize)>,
+}
+
+fn shannon_entropy(token: &str) -> f64 {
+    let mut counts = std::collections::HashMap::new();
+    for c in token.chars() {
+        *counts.entry(c).or_insert(0usize) += 1;
+    }
+    let length = token.chars().count() as f64;
+    return counts
+        .values()
+        .map(|&n| {
+            let p = n as f64 / length;
+            -p * p.log2()
+        })
+        .sum();
+}
+
+/// Find random looking tokens, like generated passwords or API keys.
+/// Paths and hex strings (commit ids, UUIDs, checksums) are ignored.
+pub fn find_high_entropy(text: &str) -> Vec<(usize, usize)> {
+    let is_separator = |c: char| c.is_whitespace() || "=:'\",;()<>".contains(c);
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (i, c) in text
+        .char_indices()
+        .chain(std::iter::once((text.len(), ' ')))
+    {
+        match (start, is_separator(c)) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                let token = &text[s..i];
+                start = None;
+                if token.len() < ENTROPY_MIN_LENGTH || token.contains('/') {
+                    continue;
+                }
+                if token.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+                    continue;
+                }
+                let classes = [
+                    token.chars().any(|c| c.is_ascii_lowercase()),
+                    token.chars().any(|c| c.is_ascii_uppercase()),
+                    token.chars().any(|c| c.is_ascii_digit()),
+                ];
+                if classes.iter().filter(|c| **c).count() >= 2
+                    && shannon_entropy(token) >= ENTROPY_THRESHOLD
+                {
+                    ranges.push((s, i));
+                }
+            }
+            _ => {}
+        }
+    }
+    return ranges;
+}
+
+/// Check a command with all detectors: the patterns (known token prefixes, `password=`, ...)
+/// and the entropy of its tokens
+pub fn detect_secrets(argv: &str, patterns: &[Regex]) -> (Vec<&'static str>, Vec<(usize, usize)>) {
+    let mut detectors = Vec::new();
+    // secrets masked before, e.g. by a rewrite, are no findings anymore
+    let mut ranges: Vec<(usize, usize)> = find_secrets(argv, patterns)
+        .into_iter()
+        .filter(|(start, end)| !argv[*start..*end].chars().all(|c| c == '*'))
+        .collect();
+    if !ranges.is_empty() {
+        detectors.push("pattern");
+    }
+    let random = find_high_entropy(argv);
+    if !random.is_empty() {
+        detectors.push("entropy");
+        ranges.extend(random);
+    }
+    return (detectors, ranges);
+}
+
+/// Check every command of the database and the notes of the commands
+pub fn scan_database(conn: &Connection, patterns: &[Regex]) -> Result<Vec<SecretFinding>> {
+    let mut findings = Vec::new();
+    let mut texts = vec![(
+        "select id, argv from commands where argv is not null order by id",
+        false,
+    )];
+    if has_annotations(conn, "main")? {
+        texts.push((
+            "select command_id, note from command_notes where note is not null order by command_id",
+            true,
+        ));
+    }
+    let mut history_stmt =
+        conn.prepare("select id from history where command_id = ?1 order by id")?;
+    for (query, note) in texts {
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (command_id, argv): (i64, String) = row?;
+            let (detectors, ranges) = detect_secrets(&argv, patterns);
+            if detectors.is_empty() {
+                continue;
+            }
+            let history_ids = history_stmt
+                .query_map(params![command_id], |row| row.get(0))?
+                .collect::<Result<Vec<i64>>>()?;
+            findings.push(SecretFinding {
+                command_id,
+                argv,
+                note,
+                history_ids,
+                detectors,
+                ranges,
+            });
+        }
+    }
+    return Ok(findings);
+}
+
+/// Delete the commands with their history entries, tags and notes, or if `rewrite` is set,
+/// replace them by their masked version, which gets their tags and notes. Notes are deleted or
+/// masked on their own. Returns the number of history entries and notes changed.
+pub fn purge(conn: &mut Connection, findings: &[SecretFinding], rewrite: bool) -> Result<usize> {
+    let tx = conn.transaction()?;
+    let annotated = has_annotations(&tx, "main")?;
+    let mut changed = 0;
+    // the notes first, so a masked command does not get the secrets of the note
+    for finding in findings.iter().filter(|f| f.note) {
+        if rewrite {
+            let masked = mask_ranges(&finding.argv, &finding.ranges);
+            changed += tx.execute(
+                "update command_notes set note = ?1 where command_id = ?2",
+                params![masked, finding.command_id],
+            )?;
+        } else {
+            changed += tx.execute(
+                "delete from command_notes where command_id = ?1",
+                params![finding.command_id],
+            )?;
+        }
+    }
+    for finding in findings.iter().filter(|f| !f.note) {
+        if rewrite {
+            let masked = mask_ranges(&finding.argv, &finding.ranges);
+            let masked_id = insert_command(&tx, &masked)?;
+            changed += tx.execute(
+                "update history set command_id = ?1 where command_id = ?2",
+                params![masked_id, finding.command_id],
+            )?;
+            if annotated {
+                tx.execute(
+                    "insert into command_tags (command_id, tag) select ?1, tag from command_tags where command_id = ?2",
+                    params![masked_id, finding.command_id],
+                )?;
+                // a note of the masked command is kept
+                tx.execute(
+                    "insert or ignore into command_notes (command_id, note) select ?1, note from command_notes where command_id = ?2",
+                    params![masked_id, finding.command_id],
+                )?;
+            }
+        } else {
+            changed += tx.execute(
+                "delete from history where command_id = ?1",
+                params![finding.command_id],
+            )?;
+        }
+        let deleted = tx.execute(
+            "delete from commands where id = ?1 and not exists (select 1 from history where command_id = ?1)",
+            params![finding.command_id],
+        )?;
+        if deleted > 0 && annotated {
+            tx.execute(
+                "delete from command_tags where command_id = ?1",
+                params![finding.command_id],
+            )?;
+            tx.execute(
+                "delete from command_notes where command_id = ?1",
+                params![finding.command_id],
+            )?;
+        }
+    }
+    // commands imported without start time, whose history entries were deleted
+    ensure_import_schema(&tx)?;
+    tx.execute(
+        "delete from untimed_imports where history_id not in (select id from history)",
+        [],
+    )?;
+    tx.commit()?;
+    return Ok(changed);
+}
+
+/// `scan-secrets [--purge [--rewrite]]`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let (patterns, errors) = compile_patterns(
+        BUILTIN_PATTERNS
+            .iter()
+            .map(|p| p.to_string())
+            .chain(get_config().redact.patterns.iter().cloned()),
+    );
+    for error in errors.iter().chain(get_config_warning()) {
+        eprintln!("{}", error);
+    }
+    let purge_findings = has_flag(args, "--purge");
+    let database = get_histdb_database();
+    // only a purge may write to the database, or create it
+    let mut conn = match purge_findings {
+        true => open_writable(&database),
+        false => Connection::open_with_flags(&database, OpenFlags::SQLITE_OPEN_READ_ONLY),
+    }
+    .map_err(|e| format!("Cannot open database {}: {}", database, e))?;
+    let findings = scan_database(&conn, &patterns).map_err(|e| e.to_string())?;
+    for finding in &findings {
+        let ids: Vec<String> = finding.history_ids.iter().map(|i| i.to_string()).collect();
+        println!(
+            "{}\t{}\t{}{}",
+            ids.join(","),
+            finding.detectors.join(","),
+            match finding.note {
+                true => "note: ",
+                false => "",
+            },
+            mask_ranges(&finding.argv, &finding.ranges)
+        );
+    }
+    if purge_findings {
+        let rewrite = has_flag(args, "--rewrite");
+        let changed = purge(&mut conn, &findings, rewrite).map_err(|e| e.to_string())?;
+        eprintln!(
+            "{} {} history entries and notes",
+            match rewrite {
+                true => "Rewrote",
+                false => "Deleted",
+            },
+            changed
+        );
+    } else {
+        eprintln!("Found secrets in {} commands", findings.len());
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns() -> Vec<Regex> {
+        return compile_patterns(BUILTIN_PATTERNS.iter().map(|p| p.to_string())).0;
+    }
+
+    #[test]
+    fn entropy_detector() {
+        assert_eq!(
+            find_high_entropy("curl https://api?key=Zm9vYmFyYmF6cXV4MTIzNDU2Nzg5 -v"),
+            vec![(21, 49)]
+        );
+        assert!(
+            find_high_entropy("git checkout 3f2a9b1c4d5e6f708192a3b4c5d6e7f8091a2b3c").is_empty()
+        );
+        assert!(find_high_entropy("ls /usr/local/share/applications/SomeThing2").is_empty());
+        assert!(find_high_entropy("cargo build --workspace --all-targets").is_empty());
+    }
+
+    #[test]
+    fn detectors() {
+        let (detectors, _) = detect_secrets("mysql --password=hunter2", &patterns());
+        assert_eq!(detectors, vec!["pattern"]);
+        let (detectors, _) = detect_secrets("echo ok", &patterns());
+        assert!(detectors.is_empty());
+    }
+
+    fn commands(conn: &Connection) -> Vec<String> {
+        return conn
+            .prepare("select argv from commands order by id")
+            .unwrap()
+            .query_map([], |r| r.get(0))
+            .unwrap()
+            .flatten()
+            .collect();
+    }
+
+    #[test]
+    fn purge_and_rewrite() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        add_history(&conn, 1, "ls", "host", "/tmp", 1);
+        add_history(&conn, 1, "export API_TOKEN=abc", "host", "/tmp", 2);
+        add_history(&conn, 1, "export API_TOKEN=abc", "host", "/tmp", 3);
+
+        let findings = scan_database(&conn, &patterns()).unwrap();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].history_ids, vec![2, 3]);
+
+        assert_eq!(purge(&mut conn, &findings, true).unwrap(), 2);
+        assert_eq!(commands(&conn), vec!["ls", "export API_TOKEN=***"]);
+        assert!(scan_database(&conn, &patterns()).unwrap().is_empty());
+
+        add_history(
+            &conn,
+            1,
+            "curl -u user --password secret",
+            "host",
+            "/tmp",
+            4,
+        );
+        let findings = scan_database(&conn, &patterns()).unwrap();
+        assert_eq!(purge(&mut conn, &findings, false).unwrap(), 1);
+        assert_eq!(commands(&conn), vec!["ls", "export API_TOKEN=***"]);
+    }
+
+    fn annotations(conn: &Connection, table: &str) -> Vec<(i64, String)> {
+        return conn
+            .prepare(&format!("select * from {} order by command_id", table))
+            .unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .flatten()
+            .collect();
+    }
+
+    #[test]
+    fn purge_tags_and_notes() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        ensure_schema(&conn).unwrap();
+        ensure_annotation_schema(&conn).unwrap();
+        add_history(&conn, 1, "ls", "host", "/tmp", 1);
+        add_history(&conn, 1, "export API_TOKEN=abc", "host", "/tmp", 2);
+        add_history(
+            &conn,
+            1,
+            "curl -u user --password secret",
+            "host",
+            "/tmp",
+            3,
+        );
+        crate::annotation::save_annotation(&conn, 1, &[], "password=hunter2").unwrap();
+        crate::annotation::save_annotation(&conn, 2, &[String::from("deploy")], "CI").unwrap();
+        crate::annotation::save_annotation(&conn, 3, &[String::from("api")], "").unwrap();
+
+        let findings = scan_database(&conn, &patterns()).unwrap();
+        assert_eq!(findings.len(), 3);
+        assert!(findings[2].note);
+        assert_eq!(findings[2].history_ids, vec![1]);
+
+        // the note and two history entries
+        assert_eq!(purge(&mut conn, &findings, true).unwrap(), 3);
+        assert_eq!(
+            annotations(&conn, "command_notes"),
+            vec![
+                (1, String::from("password=*******")),
+                (4, String::from("CI"))
+            ]
+        );
+        assert_eq!(
+            annotations(&conn, "command_tags"),
+            vec![(4, String::from("deploy")), (5, String::from("api"))]
+        );
+        assert!(scan_database(&conn, &patterns()).unwrap().is_empty());
+
+        add_history(&conn, 1, "echo token=abc", "host", "/tmp", 4);
+        crate::annotation::save_annotation(&conn, 6, &[String::from("echo")], "").unwrap();
+        let findings = scan_database(&conn, &patterns()).unwrap();
+        assert_eq!(purge(&mut conn, &findings, false).unwrap(), 1);
+        assert_eq!(annotations(&conn, "command_tags").len(), 2);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::environment::*;
+use crate::focus::*;
+use crate::grouping::{grouping_name, parse_grouping, Grouping};
+use crate::location::{location_name, parse_location, Location};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// State files of sessions which were not used for this long are removed
+const STATE_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Location, grouping and focus of the picker, kept per shell session if
+/// HISTDB_SKIM_PERSIST_STATE is set
+#[derive(Default, PartialEq, Debug)]
+pub struct State {
+    pub location: Option<Location>,
+    pub grouping: Option<Grouping>,
+    pub focus_session: Option<String>,
+    pub focus_session_source: Option<String>,
+    pub focus_dir: Option<String>,
+    pub focus_source: Option<String>,
+    pub focus_host: Option<String>,
+}
+
+impl State {
+    pub fn parse(content: &str) -> State {
+        let mut state = State::default();
+        for line in content.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            let value = value.to_string();
+            match key {
+                "location" => state.location = Some(parse_location(&value)),
+                "grouping" => state.grouping = Some(parse_grouping(&value)),
+                "focus_session" => state.focus_session = Some(value),
+                "focus_session_source" => state.focus_session_source = Some(value),
+                "focus_dir" => state.focus_dir = Some(value),
+                "focus_source" => state.focus_source = Some(value),
+                "focus_host" => state.focus_host = Some(value),
+                _ => (),
+            }
+        }
+        return state;
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut lines = Vec::new();
+        if let Some(location) = &self.location {
+            lines.push(format!("location={}", location_name(location)));
+        }
+        if let Some(grouping) = &self.grouping {
+            lines.push(format!("grouping={}", grouping_name(grouping)));
+        }
+        let values = [
+            ("focus_session", &self.focus_session),
+            ("focus_session_source", &self.focus_session_source),
+            ("focus_dir", &self.focus_dir),
+            ("focus_source", &self.focus_source),
+            ("focus_host", &self.focus_host),
+        ];
+        for (key, value) in values {
+            if let Some(value) = value {
+                lines.push(format!("{}={}", key, value));
+            }
+        }
+        return lines.join("\n") + "\n";
+    }
+}
+
+fn get_state_dir() -> PathBuf {
+    let state_home = env::var("XDG_STATE_HOME").unwrap_or(format!(
+        "{}/.local/state",
+        env::var("HOME").unwrap_or_default()
+    ));
+    return PathBuf::from(state_home).join("zsh-histdb-skim");
+}
+
+/// The state file of the current session, None if the state is not persisted
+fn get_state_file() -> Option<PathBuf> {
+    let session = get_current_session_id();
+    if !get_persist_state_option() || session.is_empty() {
+        return None;
+    }
+    return Some(get_state_dir().join(format!("session-{}", session)));
+}
+
+/// Load the state of the current session and restore its focus
+pub fn load_state() -> State {
+    let content = match get_state_file().map(fs::read_to_string) {
+        Some(Ok(content)) => content,
+        _ => return State::default(),
+    };
+    let state = State::parse(&content);
+    if let Some(session) = &state.focus_session {
+        focus_session(session, state.focus_session_source.as_deref());
+    }
+    if let Some(dir) = &state.focus_dir {
+        focus_dir(dir);
+    }
+    if let Some(source) = &state.focus_source {
+        focus_source(source);
+    }
+    if let Some(host) = &state.focus_host {
+        focus_host(host);
+    }
+    return state;
+}
+
+/// Save the location, grouping and the current focus for the next invocation in this session
+pub fn save_state(location: &Location, grouping: Grouping) {
+    let file = match get_state_file() {
+        Some(file) => file,
+        None => return,
+    };
+    let state = State {
+        location: Some(*location),
+        grouping: Some(grouping),
+        focus_session: get_focus_session(),
+        focus_session_source: get_focus_session_source(),
+        focus_dir: get_focus_dir(),
+        focus_source: get_focus_source(),
+        focus_host: get_focus_host(),
+    };
+    let dir = get_state_dir();
+    if fs::create_dir_all(&dir).is_err() {
+        return;
+    }
+    let _ = fs::write(file, state.serialize());
+    remove_stale_states(&dir);
+}
+
+/// Sessions end without notice, so their files are removed after a while
+fn remove_stale_states(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        if !entry.file_name().to_string_lossy().starts_with("session-") {
+            continue;
+        }
+        let modified = entry.metadata().and_then(|m| m.modified());
+        if let Ok(age) = modified.map(|m| now.duration_since(m).unwrap_or_default()) {
+            if age > STATE_MAX_AGE {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let state = State {
+            location: Some(Location::Machine),
+            grouping: Some(Grouping::CommandHost),
+            focus_session: None,
+            focus_session_source: None,
+            focus_dir: Some(String::from("/home/user/some=dir")),
+            focus_source: Some(String::from("container")),
+            focus_host: Some(String::from("buildbox-3")),
+        };
+        assert_eq!(State::parse(&state.serialize()), state);
+        assert_eq!(State::parse("garbage\nunknown=1\n"), State::default());
+    }
+}
@@ -0,0 +1,103 @@
+use crate::environment::get_theme_option;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// The colors of the picker. Styles are the parameters of an SGR escape sequence, e.g. `1`
+/// for bold, an empty style leaves the text as it is.
+pub struct Theme {
+    pub name: &'static str,
+    /// The color option of skim, for the list, the prompt and the default header color
+    pub skim: &'static str,
+    /// Field names in the preview
+    pub label: &'static str,
+    /// Values which are not known, e.g. the exit status of a running command
+    pub missing: &'static str,
+    pub success: &'static str,
+    pub failure: &'static str,
+    /// The current location and the active toggles in the header
+    pub badge: &'static str,
+}
+
+pub const THEMES: &[Theme] = &[
+    Theme {
+        name: "dark",
+        skim: "dark",
+        label: "1",
+        missing: "37;1",
+        success: "32",
+        failure: "31;1",
+        badge: "7",
+    },
+    Theme {
+        name: "light",
+        skim: "light",
+        label: "1",
+        missing: "90",
+        success: "32",
+        failure: "31",
+        badge: "7",
+    },
+    Theme {
+        name: "high-contrast",
+        skim: "16,fg:15,matched:11,current:0,current_bg:15,current_match:1,header:15,info:15,prompt:15,pointer:11",
+        label: "1;4",
+        missing: "1;33",
+        success: "1;32",
+        failure: "1;31",
+        badge: "1;7",
+    },
+    // only emphasis, like bold and reverse video, is used
+    Theme {
+        name: "no-color",
+        skim: "bw",
+        label: "1",
+        missing: "",
+        success: "",
+        failure: "",
+        badge: "7",
+    },
+];
+
+static THEME: Lazy<&'static Theme> = Lazy::new(|| get_theme(&get_theme_option()));
+
+/// Get the theme of the name, unknown names get the dark one
+pub fn get_theme(name: &str) -> &'static Theme {
+    return THEMES
+        .iter()
+        .find(|t| t.name == name.to_lowercase())
+        .unwrap_or(&THEMES[0]);
+}
+
+/// The theme selected by HISTDB_THEME or NO_COLOR
+pub fn theme() -> &'static Theme {
+    return &THEME;
+}
+
+/// Apply the style to the text
+pub fn paint(style: &str, text: &str) -> String {
+    if style.is_empty() {
+        return text.to_string();
+    }
+    return format!("\x1b[{}m{}\x1b[0m", style, text);
+}
+
+/// Remove the styles, e.g. to copy a text
+pub fn strip_escapes(text: &str) -> String {
+    static ESCAPE: Lazy<Regex> = Lazy::new(|| Regex::new("\x1b\\[[0-9;]*m").unwrap());
+    return ESCAPE.replace_all(text, "").to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn themes() {
+        assert_eq!(get_theme("Light").name, "light");
+        assert_eq!(get_theme("unknown").name, "dark");
+        let no_color = get_theme("no-color");
+        assert_eq!(paint(no_color.failure, "1"), "1");
+        assert_eq!(paint(no_color.badge, "F1"), "\x1b[7mF1\x1b[0m");
+        assert_eq!(strip_escapes(&paint("1;31", "failed")), "failed");
+    }
+}
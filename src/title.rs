@@ -1,10 +1,226 @@
 use crate::environment::*;
 use crate::focus::get_focus_dir;
+use crate::focus::get_focus_host;
 use crate::focus::get_focus_session;
+use crate::focus::get_focus_source;
+use crate::grouping::{grouping_name, Grouping};
+use crate::history::is_matching_location;
 use crate::location::Location;
+use crate::preset::has_presets;
+use crate::redact::is_revealed;
+use crate::theme::{paint, theme};
 use enum_map::enum_map;
+use skim::prelude::*;
+
+/// The boxed tabs take four lines, in a picker lower than this they are shown in a single line
+const BOXED_TABS_MIN_HEIGHT: usize = 20;
+
+/// Headers narrower than this use the compact variant
+const FULL_MIN_WIDTH: usize = 80;
+/// Headers narrower than this use the minimal variant
+const COMPACT_MIN_WIDTH: usize = 45;
+
+const LOCATIONS: [Location; 4] = [
+    Location::Session,
+    Location::Directory,
+    Location::Machine,
+    Location::Everywhere,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Variant {
+    Full,
+    Compact,
+    Minimal,
+}
+
+impl Variant {
+    fn for_width(width: usize) -> Variant {
+        if width >= FULL_MIN_WIDTH {
+            return Variant::Full;
+        } else if width >= COMPACT_MIN_WIDTH {
+            return Variant::Compact;
+        }
+        return Variant::Minimal;
+    }
+
+    /// The names of the tabs, the minimal variant only names the current one
+    fn tab_names(&self, current: usize) -> Vec<&'static str> {
+        let names = match self {
+            Variant::Full => ["F1: Session", "F2: Directory", "F3: Host", "F4: Everywhere"],
+            Variant::Compact => ["F1 Session", "F2 Dir", "F3 Host", "F4 All"],
+            Variant::Minimal => ["F1 Session", "F2 Dir", "F3 Host", "F4 All"],
+        };
+        return names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| match (self, i == current) {
+                (Variant::Minimal, false) => &name[..2],
+                _ => name,
+            })
+            .collect();
+    }
+
+    fn key_help(&self) -> &'static str {
+        return match self {
+            Variant::Full => "F5: Grouping, F6: Lock Session, F7: Lock Dir, F9: Lock Host",
+            Variant::Compact => "F5 Group, F6/F7/F9 Lock",
+            Variant::Minimal => "",
+        };
+    }
+}
+
+/// Highlight the state of a toggle
+fn badge(text: &str) -> String {
+    return format!("{} ", paint(theme().badge, &format!(" {} ", text)));
+}
+
+/// Get the width of the header, which is next to the preview window unless it is above or
+/// below the list
+fn header_width(terminal_width: usize) -> usize {
+    // skim indents the header like the entries
+    return list_width(terminal_width).saturating_sub(2);
+}
+
+fn list_width(terminal_width: usize) -> usize {
+    let window = get_preview_window_option();
+    let position = window.split(':').next().unwrap_or("right");
+    if window.split(':').any(|p| p == "hidden") || position == "up" || position == "down" {
+        return terminal_width;
+    }
+    let preview = match window
+        .split(':')
+        .find(|p| p.starts_with(|c: char| c.is_ascii_digit()))
+    {
+        Some(size) => match size.strip_suffix('%') {
+            Some(percent) => percent.parse::<usize>().unwrap_or(50) * terminal_width / 100,
+            None => size.parse::<usize>().unwrap_or(terminal_width / 2),
+        },
+        None => terminal_width / 2,
+    };
+    return terminal_width.saturating_sub(preview + 1);
+}
+
+/// Append the text to the line separated by a space, if it still fits into the width
+fn append_if_fits(line: &mut String, text: &str, width: usize) {
+    if !text.is_empty() && line.chars().count() + text.chars().count() + 1 < width {
+        line.push(' ');
+        line.push_str(text);
+    }
+}
+
+/// The locations as tabs hanging down from the prompt, the current one is open to the list
+fn boxed_tabs(names: &[&str], current: usize, key_help: &str, width: usize) -> String {
+    let count = names.len();
+    let mut top = String::from(" ");
+    let mut middle = String::from(" ");
+    let mut bottom = String::from("━");
+    for i in 0..=count {
+        // the edges left and right of the tab i
+        let left_active = i > 0 && i - 1 == current;
+        let right_active = i == current;
+        top.push(match (i, left_active, right_active) {
+            (0, _, true) => '┏',
+            (0, _, false) => '┌',
+            (i, true, _) if i == count => '┓',
+            (i, false, _) if i == count => '┐',
+            (_, _, true) => '┲',
+            (_, true, _) => '┱',
+            _ => '┬',
+        });
+        middle.push(match left_active || right_active {
+            true => '┃',
+            false => '│',
+        });
+        bottom.push(match (left_active, right_active) {
+            (_, true) => '┛',
+            (true, _) => '┗',
+            _ => '┷',
+        });
+        if let Some(name) = names.get(i) {
+            let length = name.chars().count();
+            let (line, gap) = match i == current {
+                true => ("━", " "),
+                false => ("─", "━"),
+            };
+            top.push_str(&line.repeat(length));
+            middle.push_str(name);
+            bottom.push_str(&gap.repeat(length));
+        }
+    }
+    append_if_fits(&mut middle, key_help, width);
+    let rest = width.saturating_sub(bottom.chars().count() + 1);
+    bottom.push_str(&"━".repeat(rest));
+    return format!("{}\n{}\n{}", top, middle, bottom);
+}
+
+/// The locations in a single line, the current one is highlighted
+fn single_line_tabs(names: &[&str], current: usize, key_help: &str, width: usize) -> String {
+    let mut line = String::new();
+    for (i, name) in names.iter().enumerate() {
+        match i == current {
+            true => line.push_str(&paint(theme().badge, &format!(" {} ", name))),
+            false => line.push_str(&format!(" {} ", name)),
+        }
+        line.push('│');
+    }
+    // the escape sequences do not take any space
+    let escapes = paint(theme().badge, "").chars().count();
+    append_if_fits(&mut line, key_help, width + escapes);
+    return line;
+}
+
+/// A line of the header. They are sent to skim as the first items, as it ignores the escape
+/// sequences of the header option.
+pub struct HeaderLine {
+    text: String,
+}
+
+impl HeaderLine {
+    pub fn new(text: &str) -> HeaderLine {
+        return HeaderLine {
+            text: text.to_string(),
+        };
+    }
+}
+
+impl SkimItem for HeaderLine {
+    fn text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.text)
+    }
+
+    fn display<'a>(&'a self, _context: DisplayContext<'a>) -> AnsiString<'a> {
+        AnsiString::parse(&self.text)
+    }
+}
+
+/// Join the lines of an error, like the ones of the config and regex parsers, for the single
+/// line of the header. The lines quoting the input and pointing at it are left out.
+fn single_line(error: &str) -> String {
+    return error
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| {
+            !l.is_empty() && !l.starts_with('|') && !l.contains(" | ") && !l.starts_with('^')
+        })
+        .collect::<Vec<&str>>()
+        .join(" ");
+}
+
+/// Generate the header of the picker, `width` is the one of the terminal and `height` the
+/// number of lines of the picker. A `message` like an error is shown instead of the filter.
+pub fn generate_title(
+    location: &Location,
+    grouping: Grouping,
+    preset: Option<&str>,
+    qualifiers: &str,
+    message: Option<&str>,
+    width: usize,
+    height: usize,
+) -> String {
+    let width = header_width(width);
+    let variant = Variant::for_width(width);
 
-pub fn generate_title(location: &Location) -> String {
     let extra_info = |theloc: &Location| -> String {
         return match theloc {
             Location::Session => get_current_session_id(),
@@ -12,52 +228,140 @@ pub fn generate_title(location: &Location) -> String {
             Location::Machine => get_current_host(),
             _ => String::from(""),
         };
-    }(&location);
+    }(location);
 
-    let format_extra_info = |info: Option<String>, title: &str| -> String {
+    let format_badge = |info: Option<String>, title: &str| -> String {
         return match info {
-            Some(ri) => format!("{}: {} ", &title, &ri,),
+            Some(ri) => badge(&format!("{}: {}", &title, &ri)),
             None => String::from(""),
         };
     };
-    let focus_session = format_extra_info(get_focus_session(), "Session");
-    let focus_dir = format_extra_info(get_focus_dir(), "Directory");
+    let grouping = format_badge(Some(grouping_name(&grouping).to_string()), "Grouping");
+    let focus_session = format_badge(get_focus_session(), "Session");
+    let focus_dir = format_badge(get_focus_dir(), "Directory");
+    let focus_host = format_badge(get_focus_host(), "Host");
+    let focus_source = match get_histdb_databases().len() {
+        1 => String::from(""),
+        _ => format_badge(
+            Some(get_focus_source().unwrap_or(String::from("all"))),
+            "F8: Source",
+        ),
+    };
 
-    let location_map = enum_map! {
-        Location::Session => "Session location history",
-        Location::Directory => "Directory location history",
-        Location::Machine => "Machine location history",
-        Location::Everywhere => "Everywhere",
+    let preset = match has_presets() {
+        true => format_badge(Some(preset.unwrap_or("none").to_string()), "alt-p: Preset"),
+        false => String::from(""),
     };
 
-    let header_map = enum_map! {
-        Location::Session =>
-" ┏━━━━━━━━━━━┱─────────────┬────────┬──────────────┐
- ┃F1: Session┃F2: Directory│F3: Host│F4: Everywhere│ F5: Toggle group, F6: Lock Session, F7: Lock Dir
-━┛           ┗━━━━━━━━━━━━━┷━━━━━━━━┷━━━━━━━━━━━━━━┷━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━",
-        Location::Directory =>
-" ┌───────────┲━━━━━━━━━━━━━┱────────┬──────────────┐
- │F1: Session┃F2: Directory┃F3: Host│F4: Everywhere│ F5: Toggle group, F6: Lock Session, F7: Lock Dir
-━┷━━━━━━━━━━━┛             ┗━━━━━━━━┷━━━━━━━━━━━━━━┷━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━",
+    let qualifiers = match (message, qualifiers.is_empty()) {
+        (Some(error), _) => format!("{} ", single_line(error)),
+        (None, true) => String::from(""),
+        (None, false) => format!("Filter: {} (alt-q: clear) ", qualifiers),
+    };
 
-        Location::Machine =>
-" ┌───────────┬─────────────┲━━━━━━━━┱──────────────┐
- │F1: Session│F2: Directory┃F3: Host┃F4: Everywhere│ F5: Toggle group, F6: Lock Session, F7: Lock Dir
-━┷━━━━━━━━━━━┷━━━━━━━━━━━━━┛        ┗━━━━━━━━━━━━━━┷━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━",
+    let match_location = match is_matching_location() {
+        true => badge("alt-m: Matching dir/host"),
+        false => String::from(""),
+    };
 
-        Location::Everywhere =>
-" ┌───────────┬─────────────┬────────┲━━━━━━━━━━━━━━┓
- │F1: Session│F2: Directory│F3: Host┃F4: Everywhere┃ F5: Toggle group, F6: Lock Session, F7: Lock Dir
-━┷━━━━━━━━━━━┷━━━━━━━━━━━━━┷━━━━━━━━┛              ┗━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━",
+    let revealed = match is_revealed() {
+        true => badge("alt-r: Secrets revealed"),
+        false => String::from(""),
+    };
+
+    let location_map = enum_map! {
+        Location::Session => "Session location history ",
+        Location::Directory => "Directory location history ",
+        Location::Machine => "Machine location history ",
+        Location::Everywhere => "Everywhere ",
+    };
+    let location_name = match variant {
+        Variant::Full => location_map[*location],
+        _ => "",
+    };
+
+    let current = LOCATIONS.iter().position(|l| l == location).unwrap_or(0);
+    let names = variant.tab_names(current);
+    // the boxes hang down from the prompt, they can not be drawn upside down
+    let boxed = get_border_option()
+        && get_layout_option().starts_with("reverse")
+        && height >= BOXED_TABS_MIN_HEIGHT
+        && variant != Variant::Minimal;
+    let tabs = match boxed {
+        true => boxed_tabs(&names, current, variant.key_help(), width),
+        false => single_line_tabs(&names, current, variant.key_help(), width),
     };
 
     let title = format!(
-        "{} {} {}{}\n{}\n",
-        &location_map[location.clone()],
+        "{}{} {}{}{}{}{}{}{}{}{}\n{}\n",
+        location_name,
         &extra_info,
+        &grouping,
         &focus_session,
         &focus_dir,
-        &header_map[location.clone()],
+        &focus_host,
+        &focus_source,
+        &preset,
+        &qualifiers,
+        &match_location,
+        &revealed,
+        &tabs,
     );
     return title.to_string();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_in_a_single_line() {
+        assert_eq!(
+            single_line("regex parse error:\n    (unclosed\n    ^\nerror: unclosed group"),
+            "regex parse error: (unclosed error: unclosed group"
+        );
+        assert_eq!(
+            single_line("TOML parse error at line 1, column 7\n  |\n1 | bad = \n  |       ^\ninvalid string\n"),
+            "TOML parse error at line 1, column 7 invalid string"
+        );
+    }
+
+    #[test]
+    fn tabs_fit_the_width() {
+        let tabs = boxed_tabs(
+            &Variant::Full.tab_names(0),
+            0,
+            Variant::Full.key_help(),
+            120,
+        );
+        let lines: Vec<&str> = tabs.lines().collect();
+        assert_eq!(
+            lines[0],
+            " ┏━━━━━━━━━━━┱─────────────┬────────┬──────────────┐"
+        );
+        assert_eq!(
+            lines[1],
+            " ┃F1: Session┃F2: Directory│F3: Host│F4: Everywhere│ F5: Grouping, F6: Lock Session, F7: Lock Dir, F9: Lock Host"
+        );
+        assert!(lines[2].starts_with("━┛           ┗━━━━━━━━━━━━━┷━━━━━━━━┷━━━━━━━━━━━━━━┷━━━"));
+        assert_eq!(lines[2].chars().count(), 119);
+
+        // the key help does not fit anymore
+        let tabs = boxed_tabs(&Variant::Full.tab_names(3), 3, Variant::Full.key_help(), 80);
+        let lines: Vec<&str> = tabs.lines().collect();
+        assert_eq!(
+            lines[1],
+            " │F1: Session│F2: Directory│F3: Host┃F4: Everywhere┃"
+        );
+        assert!(lines[2].starts_with("━┷━━━━━━━━━━━┷━━━━━━━━━━━━━┷━━━━━━━━┛              ┗━━━"));
+
+        let minimal = Variant::Minimal.tab_names(1);
+        assert_eq!(
+            single_line_tabs(&minimal, 1, Variant::Minimal.key_help(), 30),
+            format!(" F1 │{}│ F3 │ F4 │", paint(theme().badge, " F2 Dir "))
+        );
+        assert_eq!(Variant::for_width(100), Variant::Full);
+        assert_eq!(Variant::for_width(60), Variant::Compact);
+        assert_eq!(Variant::for_width(40), Variant::Minimal);
+    }
+}
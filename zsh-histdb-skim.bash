@@ -0,0 +1,81 @@
+# bash integration of zsh-histdb-skim
+#
+# Records every command into the histdb database, like zsh-histdb does for zsh, and binds
+# ctrl-r to the history picker. Source it from ~/.bashrc:
+#
+#   source /path/to/zsh-histdb-skim/zsh-histdb-skim.bash
+
+XDG_BIN_PATH=${XDG_DATA_HOME:-$HOME/.local/share}/zsh-histdb-skim/
+BIN_DIR=${HISTDB_SKIM_PATH:-${XDG_BIN_PATH}}
+BIN_PATH=${BIN_DIR}/zsh-histdb-skim
+
+HISTDB_FILE=${HISTDB_FILE:-$HOME/.histdb/zsh-history.db}
+HISTDB_HOST=${HISTDB_HOST:-$HOSTNAME}
+
+_histdb_skim_record() {
+  HISTDB_HOST=${HISTDB_HOST} \
+  HISTDB_SESSION=${HISTDB_SESSION} \
+  HISTDB_FILE=${HISTDB_FILE} \
+  "${BIN_PATH}" record "$@"
+}
+
+_histdb_skim_init() {
+  # every shell is a session of its own, so it is not exported to child shells
+  HISTDB_SESSION=$(_histdb_skim_record session)
+}
+
+# sets _HISTDB_SKIM_ENTRY to the last entry of the bash history with its number and time,
+# and _HISTDB_SKIM_CMD to its command
+_histdb_skim_last_entry() {
+  _HISTDB_SKIM_ENTRY=$(HISTTIMEFORMAT='%s ' builtin history 1)
+  if [[ "${_HISTDB_SKIM_ENTRY}" =~ ^\ *[0-9]+[\ \*]+([0-9]+\ |\?\?)(.*)$ ]]; then
+    _HISTDB_SKIM_CMD=${BASH_REMATCH[2]}
+  fi
+}
+
+# called by the DEBUG trap before every command, only the first one of a line is recorded
+_histdb_skim_preexec() {
+  [[ -n "${COMP_LINE}" || -n "${_HISTDB_SKIM_ID}" || -z "${_HISTDB_SKIM_READY}" ]] && return
+  [[ "${BASH_COMMAND}" == _histdb_skim_* ]] && return
+
+  # commands not added to the history (e.g. by HISTCONTROL=ignorespace) are not recorded,
+  # neither are key bindings like the widget. The number alone does not tell, with
+  # HISTCONTROL=erasedups a repeated command replaces its earlier entry and keeps the number.
+  local last_entry=${_HISTDB_SKIM_ENTRY}
+  _histdb_skim_last_entry
+  [[ "${_HISTDB_SKIM_ENTRY}" == "${last_entry}" ]] && return
+  _HISTDB_SKIM_READY=
+
+  _HISTDB_SKIM_ID=$(_histdb_skim_record start -- "${_HISTDB_SKIM_CMD}")
+}
+
+# called by PROMPT_COMMAND after every command line
+_histdb_skim_precmd() {
+  local exit_status=$?
+  if [[ -n "${_HISTDB_SKIM_ID}" ]]; then
+    _histdb_skim_record finish "${_HISTDB_SKIM_ID}" "${exit_status}"
+  fi
+  _HISTDB_SKIM_ID=
+  _HISTDB_SKIM_READY=1
+  return ${exit_status}
+}
+
+histdb-skim-widget() {
+  local output
+  if output=$( \
+    HISTDB_HOST=${HISTDB_HOST} \
+    HISTDB_SESSION=${HISTDB_SESSION} \
+    HISTDB_FILE=${HISTDB_SKIM_FILES:-$HISTDB_FILE} \
+    "${BIN_PATH}" -- "${READLINE_LINE}" \
+  ); then
+    READLINE_LINE=${output}
+  fi
+  READLINE_POINT=${#READLINE_LINE}
+}
+
+_histdb_skim_init
+_histdb_skim_last_entry
+PROMPT_COMMAND="_histdb_skim_precmd${PROMPT_COMMAND:+; ${PROMPT_COMMAND}}"
+trap '_histdb_skim_preexec' DEBUG
+
+bind -x '"\C-r": histdb-skim-widget'
@@ -0,0 +1,63 @@
+# fish integration of zsh-histdb-skim
+#
+# Records every command into the histdb database, like zsh-histdb does for zsh, and binds
+# ctrl-r to the history picker. Source it from ~/.config/fish/config.fish:
+#
+#   source /path/to/zsh-histdb-skim/zsh-histdb-skim.fish
+
+set -q XDG_DATA_HOME; or set -l XDG_DATA_HOME $HOME/.local/share
+set -q HISTDB_SKIM_PATH; or set -l HISTDB_SKIM_PATH $XDG_DATA_HOME/zsh-histdb-skim
+set -g HISTDB_SKIM_BIN_PATH $HISTDB_SKIM_PATH/zsh-histdb-skim
+
+set -q HISTDB_FILE; or set -g HISTDB_FILE $HOME/.histdb/zsh-history.db
+set -q HISTDB_HOST; or set -g HISTDB_HOST (hostname)
+
+function _histdb_skim_record
+    HISTDB_HOST=$HISTDB_HOST \
+    HISTDB_SESSION=$HISTDB_SESSION \
+    HISTDB_FILE=$HISTDB_FILE \
+    $HISTDB_SKIM_BIN_PATH record $argv
+end
+
+function _histdb_skim_init
+    # every shell is a session of its own, so it is not exported to child shells
+    set -g HISTDB_SESSION (_histdb_skim_record session)
+end
+
+function _histdb_skim_preexec --on-event fish_preexec
+    set -e _histdb_skim_id
+    # like the fish history, commands starting with a space are not recorded
+    if string match -qr '^\s' -- $argv[1]; or test -z (string trim -- $argv[1])
+        return
+    end
+    set -g _histdb_skim_id (_histdb_skim_record start -- $argv[1])
+end
+
+function _histdb_skim_postexec --on-event fish_postexec
+    set -l exit_status $status
+    if set -q _histdb_skim_id
+        _histdb_skim_record finish $_histdb_skim_id $exit_status
+        set -e _histdb_skim_id
+    end
+end
+
+function histdb-skim-widget
+    set -l origquery (commandline | string collect)
+    set -l files $HISTDB_FILE
+    set -q HISTDB_SKIM_FILES; and set files $HISTDB_SKIM_FILES
+    set -l output (
+        HISTDB_HOST=$HISTDB_HOST \
+        HISTDB_SESSION=$HISTDB_SESSION \
+        HISTDB_FILE=$files \
+        $HISTDB_SKIM_BIN_PATH -- "$origquery"
+    )
+    if test $status -eq 0
+        commandline -r -- (string join \n -- $output)
+    end
+    commandline -f repaint
+end
+
+_histdb_skim_init
+
+bind \cr histdb-skim-widget
+bind -M insert \cr histdb-skim-widget